@@ -0,0 +1,175 @@
+use crate::{capabilities::Capabilities, runner::Runner};
+use failure::bail;
+use std::{ffi::OsString, fmt, path::Path, str};
+
+/// The render device used for VAAPI.
+pub const VAAPI_DEVICE: &str = "/dev/dri/renderD128";
+
+/// Hardware decoders of each backend, by the codec they decode as named by ffprobe.
+///
+/// VAAPI picks its decoder through `-hwaccel` alone.
+const DECODERS: &[(Backend, &str, &str)] = &[
+    (Backend::Nvidia, "h264", "h264_cuvid"),
+    (Backend::Nvidia, "hevc", "hevc_cuvid"),
+    (Backend::Nvidia, "vp8", "vp8_cuvid"),
+    (Backend::Nvidia, "vp9", "vp9_cuvid"),
+    (Backend::Nvidia, "av1", "av1_cuvid"),
+    (Backend::Nvidia, "mpeg2video", "mpeg2_cuvid"),
+    (Backend::Nvidia, "mpeg4", "mpeg4_cuvid"),
+    (Backend::Nvidia, "vc1", "vc1_cuvid"),
+    (Backend::Nvidia, "mjpeg", "mjpeg_cuvid"),
+    (Backend::Qsv, "h264", "h264_qsv"),
+    (Backend::Qsv, "hevc", "hevc_qsv"),
+    (Backend::Qsv, "vp8", "vp8_qsv"),
+    (Backend::Qsv, "vp9", "vp9_qsv"),
+    (Backend::Qsv, "av1", "av1_qsv"),
+    (Backend::Qsv, "mpeg2video", "mpeg2_qsv"),
+    (Backend::Qsv, "vc1", "vc1_qsv"),
+    (Backend::Qsv, "mjpeg", "mjpeg_qsv"),
+];
+
+/// The encoder backend used to realize a format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// NVIDIA hardware decoding (cuvid) and encoding (nvenc).
+    Nvidia,
+    /// VAAPI hardware decoding and encoding, as found on Intel and AMD.
+    Vaapi,
+    /// Intel Quick Sync Video hardware decoding and encoding.
+    Qsv,
+    /// Software decoding and encoding (libx264).
+    Software,
+}
+
+impl Backend {
+    /// Hardware backends in the order of preference they are detected in.
+    const HARDWARE: [Backend; 3] = [Backend::Nvidia, Backend::Vaapi, Backend::Qsv];
+
+    /// Detect the best available backend by test-encoding a single frame with each hardware
+    /// encoder that ffmpeg claims to support.
+    ///
+    /// Having `h264_nvenc` compiled into ffmpeg doesn't mean there is a device to run it on, so
+    /// this actually has to run an encode.
+    pub fn detect(runner: &dyn Runner, command: &Path, capabilities: &Capabilities) -> Backend {
+        for &backend in &Self::HARDWARE {
+            if backend.is_supported(capabilities) && backend.test_encode(runner, command) {
+                return backend;
+            }
+        }
+
+        Backend::Software
+    }
+
+    /// The filter used to scale frames produced by this backend.
+    pub fn scale_filter(self) -> &'static str {
+        match self {
+            Backend::Vaapi => "scale_vaapi",
+            Backend::Qsv => "scale_qsv",
+            Backend::Nvidia | Backend::Software => "scale",
+        }
+    }
+
+    /// Build a filter which scales to the given resolution with this backend.
+    pub fn scale(self, width: u32, height: u32) -> String {
+        match self {
+            Backend::Vaapi | Backend::Qsv => {
+                format!("{}=w={}:h={}", self.scale_filter(), width, height)
+            }
+            Backend::Nvidia | Backend::Software => format!("scale={}:{}", width, height),
+        }
+    }
+
+    /// The hardware decoder of this backend for a codec as named by ffprobe, if it has one.
+    pub fn decoder(self, codec: &str) -> Option<&'static str> {
+        DECODERS
+            .iter()
+            .find(|&&(backend, c, _)| backend == self && c == codec)
+            .map(|&(_, _, decoder)| decoder)
+    }
+
+    /// All hardware decoders of this backend.
+    pub fn decoders(self) -> impl Iterator<Item = &'static str> {
+        DECODERS
+            .iter()
+            .filter(move |&&(backend, _, _)| backend == self)
+            .map(|&(_, _, decoder)| decoder)
+    }
+
+    /// Test if ffmpeg was built with the decoders and encoders this backend uses.
+    fn is_supported(self, capabilities: &Capabilities) -> bool {
+        match self {
+            Backend::Nvidia => {
+                capabilities.has_encoder("h264_nvenc") && capabilities.has_decoder("h264_cuvid")
+            }
+            Backend::Vaapi => {
+                capabilities.has_encoder("h264_vaapi") && capabilities.has_hwaccel("vaapi")
+            }
+            Backend::Qsv => {
+                capabilities.has_encoder("h264_qsv") && capabilities.has_decoder("h264_qsv")
+            }
+            Backend::Software => capabilities.has_encoder("libx264"),
+        }
+    }
+
+    /// Encode a single frame of a generated source to see if the hardware is actually present.
+    fn test_encode(self, runner: &dyn Runner, command: &Path) -> bool {
+        let mut argv = vec!["-hide_banner", "-loglevel", "quiet"];
+
+        if let Backend::Vaapi = self {
+            argv.extend(["-vaapi_device", VAAPI_DEVICE]);
+        }
+
+        argv.extend(["-f", "lavfi", "-i", "color=size=256x256:duration=0.1"]);
+
+        match self {
+            Backend::Nvidia => {
+                argv.extend(["-c:v", "h264_nvenc"]);
+            }
+            Backend::Vaapi => {
+                argv.extend(["-vf", "format=nv12,hwupload", "-c:v", "h264_vaapi"]);
+            }
+            Backend::Qsv => {
+                argv.extend(["-vf", "format=nv12", "-c:v", "h264_qsv"]);
+            }
+            Backend::Software => {
+                argv.extend(["-c:v", "libx264"]);
+            }
+        }
+
+        argv.extend(["-frames:v", "1", "-f", "null", "-"]);
+
+        let argv = std::iter::once(command.into())
+            .chain(argv.into_iter().map(OsString::from))
+            .collect::<Vec<_>>();
+
+        match runner.output(&argv) {
+            Ok(output) => output.status.success(),
+            Err(_) => false,
+        }
+    }
+}
+
+impl str::FromStr for Backend {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "nvidia" | "nvenc" | "cuvid" => Backend::Nvidia,
+            "vaapi" => Backend::Vaapi,
+            "qsv" => Backend::Qsv,
+            "software" | "none" => Backend::Software,
+            other => bail!("illegal --hwaccel: {}", other),
+        })
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Backend::Nvidia => "nvidia".fmt(fmt),
+            Backend::Vaapi => "vaapi".fmt(fmt),
+            Backend::Qsv => "qsv".fmt(fmt),
+            Backend::Software => "software".fmt(fmt),
+        }
+    }
+}
@@ -0,0 +1,37 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+#[derive(Debug, Default)]
+struct State {
+    cancelled: AtomicBool,
+    interrupted: AtomicBool,
+}
+
+/// A flag shared between everything that needs to stop when tessie is interrupted.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel(Arc<State>);
+
+impl Cancel {
+    /// Signal that everything should stop.
+    pub fn cancel(&self) {
+        self.0.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Signal that everything should stop because tessie received a signal like Ctrl-C.
+    pub fn interrupt(&self) {
+        self.0.interrupted.store(true, Ordering::SeqCst);
+        self.cancel();
+    }
+
+    /// Test if we have been cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Test if we have been interrupted by a signal.
+    pub fn is_interrupted(&self) -> bool {
+        self.0.interrupted.load(Ordering::SeqCst)
+    }
+}
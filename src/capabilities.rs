@@ -0,0 +1,168 @@
+use crate::runner::Runner;
+use failure::bail;
+use std::{collections::HashSet, ffi::OsString, fmt, path::Path};
+
+/// Things a format needs from ffmpeg to be able to run.
+#[derive(Debug, Default)]
+pub struct Requirements<'a> {
+    /// Encoders, like `libx264`.
+    pub encoders: Vec<&'a str>,
+    /// Decoders, like `h264_cuvid`.
+    pub decoders: Vec<&'a str>,
+    /// Hardware acceleration methods, like `vaapi`.
+    pub hwaccels: Vec<&'a str>,
+    /// Filters, like `scale`.
+    pub filters: Vec<&'a str>,
+}
+
+/// A single capability which is missing from ffmpeg.
+#[derive(Debug)]
+pub enum Missing<'a> {
+    /// An encoder ffmpeg wasn't built with.
+    Encoder(&'a str),
+    /// A decoder ffmpeg wasn't built with.
+    Decoder(&'a str),
+    /// A hardware acceleration method ffmpeg doesn't support.
+    HwAccel(&'a str),
+    /// A filter ffmpeg wasn't built with.
+    Filter(&'a str),
+}
+
+impl fmt::Display for Missing<'_> {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Missing::Encoder(name) => write!(fmt, "encoder `{}`", name),
+            Missing::Decoder(name) => write!(fmt, "decoder `{}`", name),
+            Missing::HwAccel(name) => write!(fmt, "hwaccel `{}`", name),
+            Missing::Filter(name) => write!(fmt, "filter `{}`", name),
+        }
+    }
+}
+
+/// The capabilities of the installed ffmpeg.
+#[derive(Debug, Default)]
+pub struct Capabilities {
+    /// Names of the encoders, as listed by `ffmpeg -encoders`.
+    pub encoders: HashSet<String>,
+    /// Names of the decoders, as listed by `ffmpeg -decoders`.
+    pub decoders: HashSet<String>,
+    /// Hardware acceleration methods, as listed by `ffmpeg -hwaccels`.
+    pub hwaccels: HashSet<String>,
+    /// Names of the filters, as listed by `ffmpeg -filters`.
+    pub filters: HashSet<String>,
+}
+
+impl Capabilities {
+    /// Probe the capabilities of the given ffmpeg command.
+    pub fn probe(runner: &dyn Runner, command: &Path) -> Result<Capabilities, failure::Error> {
+        Ok(Capabilities {
+            encoders: parse_table(&run(runner, command, "-encoders")?),
+            decoders: parse_table(&run(runner, command, "-decoders")?),
+            hwaccels: parse_list(&run(runner, command, "-hwaccels")?),
+            filters: parse_table(&run(runner, command, "-filters")?),
+        })
+    }
+
+    /// Test if the given encoder is available.
+    pub fn has_encoder(&self, name: &str) -> bool {
+        self.encoders.contains(name)
+    }
+
+    /// Test if the given decoder is available.
+    pub fn has_decoder(&self, name: &str) -> bool {
+        self.decoders.contains(name)
+    }
+
+    /// Test if the given filter is available.
+    pub fn has_filter(&self, name: &str) -> bool {
+        self.filters.contains(name)
+    }
+
+    /// Test if the given hardware acceleration method is available.
+    pub fn has_hwaccel(&self, name: &str) -> bool {
+        self.hwaccels.contains(name)
+    }
+
+    /// List everything in the requirements which is not available.
+    pub fn missing<'a>(&self, requirements: &Requirements<'a>) -> Vec<Missing<'a>> {
+        let mut missing = Vec::new();
+
+        for &name in &requirements.encoders {
+            if !self.encoders.contains(name) {
+                missing.push(Missing::Encoder(name));
+            }
+        }
+
+        for &name in &requirements.decoders {
+            if !self.decoders.contains(name) {
+                missing.push(Missing::Decoder(name));
+            }
+        }
+
+        for &name in &requirements.hwaccels {
+            if !self.hwaccels.contains(name) {
+                missing.push(Missing::HwAccel(name));
+            }
+        }
+
+        for &name in &requirements.filters {
+            if !self.filters.contains(name) {
+                missing.push(Missing::Filter(name));
+            }
+        }
+
+        missing
+    }
+}
+
+/// Run ffmpeg with a single listing option and return its stdout.
+fn run(runner: &dyn Runner, command: &Path, option: &str) -> Result<String, failure::Error> {
+    let o = runner.output(&[
+        command.into(),
+        "-hide_banner".into(),
+        OsString::from(option),
+    ])?;
+
+    if !o.status.success() {
+        bail!("could not run: `{} {}`: {:?}", command.display(), option, o);
+    }
+
+    Ok(String::from_utf8_lossy(&o.stdout).into_owned())
+}
+
+/// Parse the tables printed by `-encoders`, `-decoders` and `-filters`.
+///
+/// Each entry is an indented line of flags followed by a name, while legend lines have `=` as
+/// their second column.
+fn parse_table(output: &str) -> HashSet<String> {
+    let mut names = HashSet::new();
+
+    for line in output.lines() {
+        if !line.starts_with(' ') {
+            continue;
+        }
+
+        let mut it = line.split_whitespace();
+
+        match (it.next(), it.next()) {
+            (Some(_), Some("=")) | (_, None) => {}
+            (Some(_), Some(name)) => {
+                names.insert(name.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    names
+}
+
+/// Parse the list printed by `-hwaccels`, which is a header followed by one method per line.
+fn parse_list(output: &str) -> HashSet<String> {
+    output
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .map(String::from)
+        .collect()
+}
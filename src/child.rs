@@ -0,0 +1,77 @@
+//! Management of ffmpeg child processes.
+
+use std::{
+    io::{self, Write},
+    process, thread,
+    time::{Duration, Instant},
+};
+
+/// How long ffmpeg gets to finalize its output after being asked to quit.
+const QUIT_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long ffmpeg gets to exit after being signalled, before it is killed.
+const SIGNAL_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Configure the command so that it doesn't receive signals sent to the terminal.
+///
+/// Ctrl-C is sent to the whole foreground process group, which would stop ffmpeg before we get a
+/// chance to stop it gracefully.
+pub fn isolate(cmd: &mut process::Command) {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt as _;
+        cmd.process_group(0);
+    }
+}
+
+/// Stop a running child.
+///
+/// First asks it to quit by writing `q` to its stdin, then forwards `SIGINT` and finally kills it
+/// if it hasn't exited in time.
+pub fn stop(
+    child: &mut process::Child,
+    stdin: Option<process::ChildStdin>,
+) -> io::Result<process::ExitStatus> {
+    if let Some(mut stdin) = stdin {
+        // ffmpeg might already be exiting and have closed its stdin.
+        let _ = stdin.write_all(b"q").and_then(|_| stdin.flush());
+    }
+
+    if let Some(status) = wait_timeout(child, QUIT_TIMEOUT)? {
+        return Ok(status);
+    }
+
+    #[cfg(unix)]
+    {
+        // SAFETY: the child has not been waited for, so its pid has not been reused.
+        unsafe {
+            libc::kill(child.id() as libc::pid_t, libc::SIGINT);
+        }
+
+        if let Some(status) = wait_timeout(child, SIGNAL_TIMEOUT)? {
+            return Ok(status);
+        }
+    }
+
+    child.kill()?;
+    child.wait()
+}
+
+/// Wait for the child to exit, giving up after the given timeout.
+fn wait_timeout(
+    child: &mut process::Child,
+    timeout: Duration,
+) -> io::Result<Option<process::ExitStatus>> {
+    let started = Instant::now();
+
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        if started.elapsed() >= timeout {
+            return Ok(None);
+        }
+
+        thread::sleep(Duration::from_millis(50));
+    }
+}
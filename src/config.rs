@@ -0,0 +1,156 @@
+//! Configuration files, which define presets in addition to the built-in formats and where to find
+//! ffmpeg.
+//!
+//! The user configuration is read from `~/.config/tessie/config.toml`, and a project
+//! configuration from the closest `tessie.toml` in the current directory or any of its parents.
+
+use crate::settings::Settings;
+use failure::{bail, format_err};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+/// Name of the project configuration file.
+const PROJECT_FILE: &str = "tessie.toml";
+
+/// Configuration merged from all configuration files.
+#[derive(Debug, Default)]
+pub struct Config {
+    /// Path to the ffmpeg program.
+    pub ffmpeg: Option<PathBuf>,
+    /// Path to the ffprobe program.
+    pub ffprobe: Option<PathBuf>,
+    /// Presets by name.
+    pub presets: BTreeMap<String, Settings>,
+    /// The configuration file each preset was defined in, by name.
+    pub paths: BTreeMap<String, PathBuf>,
+}
+
+/// The contents of a single configuration file.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct File {
+    ffmpeg: Option<PathBuf>,
+    ffprobe: Option<PathBuf>,
+    #[serde(default)]
+    presets: BTreeMap<String, Settings>,
+}
+
+impl Config {
+    /// Load the user and the project configuration.
+    ///
+    /// Presets and programs in the project configuration replace those in the user configuration.
+    /// Presets may not use any of the `reserved` names, which are matched case-insensitively.
+    pub fn load(reserved: &[&str]) -> Result<Config, failure::Error> {
+        let mut config = Config::default();
+
+        let paths = user_path()
+            .into_iter()
+            .chain(project_path()?)
+            .collect::<Vec<_>>();
+
+        for path in paths {
+            config.load_file(&path, reserved)?;
+        }
+
+        Ok(config)
+    }
+
+    /// Load the given file, if it exists.
+    fn load_file(&mut self, path: &Path, reserved: &[&str]) -> Result<(), failure::Error> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => bail!("failed to read config: {}: {}", path.display(), e),
+        };
+
+        let file: File = toml::from_str(&content)
+            .map_err(|e| format_err!("invalid config: {}: {}", path.display(), e))?;
+
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+        if let Some(ffmpeg) = file.ffmpeg {
+            self.ffmpeg = Some(program(dir, ffmpeg));
+        }
+
+        if let Some(ffprobe) = file.ffprobe {
+            self.ffprobe = Some(program(dir, ffprobe));
+        }
+
+        for (name, preset) in file.presets {
+            if reserved.iter().any(|r| r.eq_ignore_ascii_case(&name)) {
+                bail!(
+                    "invalid config: {}: presets.{}: name is used by a built-in format",
+                    path.display(),
+                    name
+                );
+            }
+
+            preset.validate().map_err(|e| {
+                format_err!("invalid config: {}: presets.{}.{}", path.display(), name, e)
+            })?;
+
+            self.paths.insert(name.clone(), path.to_owned());
+            self.presets.insert(name, preset);
+        }
+
+        Ok(())
+    }
+
+    /// Find a preset by name, preferring an exact match.
+    ///
+    /// Returns the name of the preset as it was defined.
+    pub fn preset(&self, name: &str) -> Option<(&str, &Settings)> {
+        self.presets
+            .get_key_value(name)
+            .or_else(|| {
+                self.presets
+                    .iter()
+                    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            })
+            .map(|(key, preset)| (key.as_str(), preset))
+    }
+
+    /// Describe where a preset is defined for errors, like `tessie.toml: presets.Discord`.
+    pub fn location(&self, name: &str) -> String {
+        match self.paths.get(name) {
+            Some(path) => format!("{}: presets.{}", path.display(), name),
+            None => format!("presets.{}", name),
+        }
+    }
+}
+
+/// Resolve a program configured in the given directory.
+///
+/// Relative paths like `bin/ffmpeg` are relative to the configuration file, while bare names like
+/// `ffmpeg-6` are looked up in `PATH`.
+fn program(dir: &Path, path: PathBuf) -> PathBuf {
+    if path.is_relative() && path.components().count() > 1 {
+        return dir.join(path);
+    }
+
+    path
+}
+
+/// The path of the user configuration.
+fn user_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(base.join("tessie").join("config.toml"))
+}
+
+/// The path of the closest project configuration.
+fn project_path() -> Result<Option<PathBuf>, failure::Error> {
+    let dir = env::current_dir()?;
+
+    Ok(dir
+        .ancestors()
+        .map(|d| d.join(PROJECT_FILE))
+        .find(|p| p.is_file()))
+}
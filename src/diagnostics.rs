@@ -0,0 +1,306 @@
+//! Classification of ffmpeg failures from what it printed to stderr.
+
+use failure::Fail;
+use std::{collections::VecDeque, fmt, io, process::ExitStatus};
+
+/// How many lines of stderr are kept around.
+const CAPACITY: usize = 64;
+/// How many of the last lines are included in an error.
+const TAIL: usize = 8;
+
+/// A ring buffer of the last lines printed by ffmpeg.
+#[derive(Debug, Default)]
+pub(crate) struct Stderr {
+    lines: VecDeque<String>,
+}
+
+impl Stderr {
+    /// Add a line, dropping the oldest one if the buffer is full.
+    pub fn push(&mut self, line: &str) {
+        if self.lines.len() == CAPACITY {
+            self.lines.pop_front();
+        }
+
+        self.lines.push_back(line.to_string());
+    }
+
+    /// Iterate over the kept lines, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = &str> {
+        self.lines.iter().map(String::as_str)
+    }
+
+    /// Classify the failure and collect the lines which are relevant to it.
+    ///
+    /// This is the last few lines, extended to include the line the class was decided from.
+    fn diagnose(&self) -> (Class, Vec<String>) {
+        let found = self
+            .lines
+            .iter()
+            .enumerate()
+            .rev()
+            .find_map(|(index, line)| Class::classify(line).map(|class| (index, class)));
+
+        let tail = self.lines.len().saturating_sub(TAIL);
+
+        match found {
+            Some((index, class)) => {
+                let lines = self.lines.iter().skip(index.min(tail)).cloned().collect();
+                (class, lines)
+            }
+            None => (
+                Class::Unknown,
+                self.lines.iter().skip(tail).cloned().collect(),
+            ),
+        }
+    }
+}
+
+/// The class of a failed transcode.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Class {
+    /// The given encoder is not available in this build of ffmpeg.
+    UnknownEncoder(String),
+    /// A file could not be found.
+    NoSuchFile(String),
+    /// A timestamp option had an invalid value.
+    InvalidTimestamp(String),
+    /// The hardware encoder refused to open another session.
+    SessionLimit,
+    /// The output device ran out of space.
+    OutOfDisk,
+    /// Anything else.
+    Unknown,
+}
+
+impl Class {
+    /// Try to classify a single line of ffmpeg output.
+    fn classify(line: &str) -> Option<Class> {
+        if let Some(rest) = after(line, "Unknown encoder '") {
+            let encoder = rest.split('\'').next().unwrap_or_default();
+            return Some(Class::UnknownEncoder(encoder.to_string()));
+        }
+
+        if let Some(rest) = after(line, "Encoder (codec ") {
+            let codec = rest.split(')').next().unwrap_or_default();
+            return Some(Class::UnknownEncoder(codec.to_string()));
+        }
+
+        if let Some(rest) = after(line, "Invalid duration specification for ") {
+            return Some(Class::InvalidTimestamp(rest.trim().to_string()));
+        }
+
+        if line.contains("OpenEncodeSessionEx failed") || line.contains("concurrent sessions") {
+            return Some(Class::SessionLimit);
+        }
+
+        if line.contains("No space left on device") {
+            return Some(Class::OutOfDisk);
+        }
+
+        if let Some(path) = line.trim().strip_suffix(": No such file or directory") {
+            // Strip context like `[in#0 @ 0x55d0c4f6e340] Error opening input`.
+            let path = match path.find("] ") {
+                Some(n) if path.starts_with('[') => &path[n + 2..],
+                _ => path,
+            };
+
+            return Some(Class::NoSuchFile(path.to_string()));
+        }
+
+        None
+    }
+
+    /// A short identifier of the class, used in machine-readable output.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Class::UnknownEncoder(..) => "unknown_encoder",
+            Class::NoSuchFile(..) => "no_such_file",
+            Class::InvalidTimestamp(..) => "invalid_timestamp",
+            Class::SessionLimit => "session_limit",
+            Class::OutOfDisk => "out_of_disk",
+            Class::Unknown => "unknown",
+        }
+    }
+
+    /// A hint on how to address the failure.
+    pub fn hint(&self) -> Option<&'static str> {
+        Some(match self {
+            Class::UnknownEncoder(..) => {
+                "this build of ffmpeg doesn't include the encoder, try another `--hwaccel` or \
+                 install an ffmpeg which has it"
+            }
+            Class::NoSuchFile(..) => {
+                "check that the input exists and that the output directory can be written to"
+            }
+            Class::InvalidTimestamp(..) => {
+                "timestamps are written like `90`, `1:30` or `00:01:30.500`"
+            }
+            Class::SessionLimit => {
+                "consumer NVIDIA GPUs limit how many encoders can run at once, lower `--hw-jobs` \
+                 or use `--hwaccel software`"
+            }
+            Class::OutOfDisk => {
+                "free up some space, or write outputs elsewhere with `--output-dir`"
+            }
+            Class::Unknown => return None,
+        })
+    }
+}
+
+impl fmt::Display for Class {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Class::UnknownEncoder(encoder) => write!(fmt, "unknown encoder `{}`", encoder),
+            Class::NoSuchFile(path) => write!(fmt, "no such file or directory: {}", path),
+            Class::InvalidTimestamp(spec) => write!(fmt, "invalid timestamp for {}", spec),
+            Class::SessionLimit => write!(fmt, "no more hardware encoder sessions available"),
+            Class::OutOfDisk => write!(fmt, "out of disk space"),
+            Class::Unknown => write!(fmt, "ffmpeg failed"),
+        }
+    }
+}
+
+/// The error of a failed transcode.
+#[derive(Debug)]
+pub enum TranscodeError {
+    /// The transcode was interrupted before it completed.
+    Interrupted,
+    /// ffmpeg exited with an error.
+    Failed {
+        /// What kind of failure it was.
+        class: Class,
+        /// The status ffmpeg exited with.
+        status: ExitStatus,
+        /// The last relevant lines printed to stderr.
+        lines: Vec<String>,
+    },
+    /// Something went wrong while setting up or running ffmpeg.
+    Other(failure::Error),
+}
+
+impl TranscodeError {
+    /// Construct an error from how ffmpeg exited and what it printed.
+    pub(crate) fn failed(status: ExitStatus, stderr: &Stderr) -> TranscodeError {
+        let (class, lines) = stderr.diagnose();
+
+        TranscodeError::Failed {
+            class,
+            status,
+            lines,
+        }
+    }
+}
+
+impl fmt::Display for TranscodeError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TranscodeError::Interrupted => write!(fmt, "interrupted"),
+            TranscodeError::Failed { class, status, .. } => write!(fmt, "{} ({})", class, status),
+            TranscodeError::Other(e) => fmt::Display::fmt(e, fmt),
+        }
+    }
+}
+
+impl Fail for TranscodeError {
+    fn cause(&self) -> Option<&dyn Fail> {
+        match self {
+            TranscodeError::Other(e) => e.as_fail().cause(),
+            _ => None,
+        }
+    }
+}
+
+impl From<failure::Error> for TranscodeError {
+    fn from(error: failure::Error) -> Self {
+        TranscodeError::Other(error)
+    }
+}
+
+impl From<io::Error> for TranscodeError {
+    fn from(error: io::Error) -> Self {
+        TranscodeError::Other(error.into())
+    }
+}
+
+/// The remainder of the line after the given needle.
+fn after<'a>(line: &'a str, needle: &str) -> Option<&'a str> {
+    line.find(needle).map(|n| &line[n + needle.len()..])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Class, Stderr};
+
+    #[test]
+    fn classifies_lines() {
+        let cases = [
+            (
+                "[vost#0:0 @ 0x5581] Unknown encoder 'h264_nvenc'",
+                Class::UnknownEncoder("h264_nvenc".to_string()),
+            ),
+            (
+                "Encoder (codec hevc) not found for output stream #0:0",
+                Class::UnknownEncoder("hevc".to_string()),
+            ),
+            (
+                "[in#0 @ 0x55d0c4f6e340] Error opening input: missing.mkv: No such file or directory",
+                Class::NoSuchFile("Error opening input: missing.mkv".to_string()),
+            ),
+            (
+                "missing.mkv: No such file or directory",
+                Class::NoSuchFile("missing.mkv".to_string()),
+            ),
+            (
+                "Invalid duration specification for ss: 1:xx",
+                Class::InvalidTimestamp("ss: 1:xx".to_string()),
+            ),
+            (
+                "[h264_nvenc @ 0x55] OpenEncodeSessionEx failed: out of memory (10)",
+                Class::SessionLimit,
+            ),
+            (
+                "[h264_nvenc @ 0x55] The maximum number of concurrent sessions has been reached",
+                Class::SessionLimit,
+            ),
+            (
+                "av_interleaved_write_frame(): No space left on device",
+                Class::OutOfDisk,
+            ),
+        ];
+
+        for (line, class) in cases.iter() {
+            assert_eq!(Class::classify(line).as_ref(), Some(class), "{}", line);
+        }
+
+        assert_eq!(Class::classify("Conversion failed!"), None);
+    }
+
+    #[test]
+    fn diagnoses_from_last_class() {
+        let mut stderr = Stderr::default();
+        stderr.push("Unknown encoder 'h264_nvenc'");
+
+        for n in 0..10 {
+            stderr.push(&format!("line {}", n));
+        }
+
+        let (class, lines) = stderr.diagnose();
+        assert_eq!(class, Class::UnknownEncoder("h264_nvenc".to_string()));
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[0], "Unknown encoder 'h264_nvenc'");
+    }
+
+    #[test]
+    fn diagnoses_unknown_from_tail() {
+        let mut stderr = Stderr::default();
+
+        for n in 0..100 {
+            stderr.push(&format!("line {}", n));
+        }
+
+        let (class, lines) = stderr.diagnose();
+        assert_eq!(class, Class::Unknown);
+        assert_eq!(lines.first().map(String::as_str), Some("line 92"));
+        assert_eq!(lines.last().map(String::as_str), Some("line 99"));
+    }
+}
@@ -0,0 +1,67 @@
+//! Events reported while jobs are planned and run, so that callers can present them however they
+//! like.
+
+use crate::{progress::Progress, quality::Metric, target::VideoTarget};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+/// The plan for a single transcode.
+pub struct Plan<'a> {
+    /// The file being transcoded.
+    pub input: &'a Path,
+    /// Where the output ends up once the transcode succeeded.
+    pub output: &'a Path,
+    /// The name of the format.
+    pub format: String,
+    /// The name of the backend.
+    pub backend: String,
+    /// The resolution and frame rate decided for the video, if the format re-encodes it.
+    pub target: Option<&'a VideoTarget>,
+    /// The expected duration of the output in seconds, if known.
+    pub duration: Option<f64>,
+    /// The full argument vectors of the passes which run before `argv` in a multi-pass encode.
+    pub first_passes: Vec<Vec<OsString>>,
+    /// The full argument vector, including the program.
+    pub argv: Vec<OsString>,
+    /// Whether the transcode is only planned and won't run.
+    pub dry_run: bool,
+}
+
+/// The outcome of a successful transcode.
+#[derive(Debug)]
+pub struct Outcome {
+    /// The file which was transcoded.
+    pub input: PathBuf,
+    /// Where the output was written.
+    pub output: PathBuf,
+    /// Size of the output in bytes.
+    pub size: u64,
+    /// Duration of the output in seconds, as last reported by ffmpeg.
+    pub duration: Option<f64>,
+    /// Wall time the transcode took in seconds.
+    pub elapsed: f64,
+}
+
+/// Receives what happens while a job is planned or run through [`Ffmpeg`].
+///
+/// Every event is ignored by default.
+///
+/// [`Ffmpeg`]: crate::Ffmpeg
+pub trait Events {
+    /// A transcode is about to start, or has been planned during a dry run.
+    fn plan(&self, _plan: &Plan<'_>) {}
+
+    /// ffmpeg reported progress.
+    fn progress(&self, _progress: &Progress) {}
+
+    /// Something worth pointing out, like a warning printed by ffmpeg.
+    fn warning(&self, _input: &Path, _message: &str) {}
+
+    /// Samples encoded with a CRF were scored, while searching for a target quality.
+    fn quality(&self, _input: &Path, _crf: u32, _score: f64, _metric: Metric) {}
+}
+
+/// Ignores every event.
+impl Events for () {}
@@ -0,0 +1,596 @@
+//! Running ffmpeg.
+
+use crate::{
+    backend::Backend,
+    cancel::Cancel,
+    capabilities::Capabilities,
+    diagnostics::{Stderr, TranscodeError},
+    events::{Events, Outcome, Plan},
+    format::Format,
+    job::TranscodeJob,
+    passthrough,
+    programs::FFMPEG_ENV,
+    progress::{self, Progress},
+    quality::{self, Metric},
+    runner::{Runner, Spawned, System},
+    size,
+    temp::{TempDir, TempOutput},
+    version::Version,
+};
+use failure::{bail, format_err};
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    fs,
+    io::{BufRead, BufReader},
+    iter,
+    path::{Path, PathBuf},
+    sync::{mpsc, Arc},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// How many times a job is encoded before giving up on fitting its target size.
+const MAX_SIZE_ATTEMPTS: usize = 3;
+
+/// ffmpeg abstraction.
+pub struct Ffmpeg {
+    runner: Arc<dyn Runner>,
+    command: PathBuf,
+    /// The version of ffmpeg, unless it is a build from git.
+    version: Option<Version>,
+    capabilities: Capabilities,
+    backend: Backend,
+    cancel: Cancel,
+}
+
+impl Ffmpeg {
+    /// Create a new ffmpeg abstraction testing that the given command works and is recent enough.
+    ///
+    /// If no backend is specified, the best available one is detected.
+    pub fn new(
+        command: impl Into<PathBuf>,
+        backend: Option<Backend>,
+    ) -> Result<Ffmpeg, failure::Error> {
+        Self::with_runner(Arc::new(System), command, backend)
+    }
+
+    /// Create a new ffmpeg abstraction which runs the given command through the given runner.
+    pub fn with_runner(
+        runner: Arc<dyn Runner>,
+        command: impl Into<PathBuf>,
+        backend: Option<Backend>,
+    ) -> Result<Ffmpeg, failure::Error> {
+        let command = command.into();
+
+        let o = runner
+            .output(&[command.clone().into(), "-version".into()])
+            .map_err(|e| {
+                format_err!(
+                    "could not run ffmpeg: {}: {} (its location can be set with --ffmpeg or {})",
+                    command.display(),
+                    e,
+                    FFMPEG_ENV
+                )
+            })?;
+
+        if !o.status.success() {
+            bail!("could not run: `{} -version`: {:?}", command.display(), o);
+        }
+
+        let version = Version::from_banner(&String::from_utf8_lossy(&o.stdout));
+
+        if let Some(version) = version.filter(|v| *v < Version::MINIMUM) {
+            bail!(
+                "ffmpeg {} is too old: {}: tessie needs ffmpeg {} or newer",
+                version,
+                command.display(),
+                Version::MINIMUM
+            );
+        }
+
+        let capabilities = Capabilities::probe(&*runner, &command)?;
+        let backend = match backend {
+            Some(backend) => backend,
+            None => Backend::detect(&*runner, &command, &capabilities),
+        };
+
+        Ok(Ffmpeg {
+            runner,
+            command,
+            version,
+            capabilities,
+            backend,
+            cancel: Cancel::default(),
+        })
+    }
+
+    /// The backend used to realize formats.
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// The ffmpeg command being run.
+    pub fn command(&self) -> &Path {
+        &self.command
+    }
+
+    /// The version of ffmpeg, unless it is a build from git which doesn't have one.
+    pub fn version(&self) -> Option<Version> {
+        self.version
+    }
+
+    /// What ffmpeg was built with.
+    pub fn capabilities(&self) -> &Capabilities {
+        &self.capabilities
+    }
+
+    /// The handle through which running transcodes are cancelled.
+    pub fn cancel(&self) -> &Cancel {
+        &self.cancel
+    }
+
+    /// Check that ffmpeg has everything the given format needs.
+    pub fn check(&self, format: &Format) -> Result<(), failure::Error> {
+        let required = format.settings.min_ffmpeg_version()?;
+
+        if let (Some(required), Some(version)) = (required, self.version) {
+            if version < required {
+                bail!(
+                    "format {} needs ffmpeg {} or newer, but {} is version {}",
+                    format,
+                    required,
+                    self.command.display(),
+                    version
+                );
+            }
+        }
+
+        let requirements = format.requirements();
+        let missing = self.capabilities.missing(&requirements);
+
+        if !missing.is_empty() {
+            let missing = missing
+                .iter()
+                .map(|m| m.to_string())
+                .collect::<Vec<_>>()
+                .join(", ");
+
+            bail!(
+                "ffmpeg is missing what is needed for format {} (backend: {}): {}",
+                format,
+                self.backend,
+                missing
+            );
+        }
+
+        Ok(())
+    }
+
+    /// The full argument vector running the given job, including the program.
+    pub fn argv(&self, job: &TranscodeJob) -> Vec<OsString> {
+        iter::once(OsString::from(&self.command))
+            .chain(job.argv())
+            .collect()
+    }
+
+    /// Report the plan for a job, warning about passed through arguments which override options
+    /// of the format.
+    ///
+    /// `passes` are the argument vectors of every pass of the job, the last one of which writes
+    /// the output.
+    fn report_plan(
+        &self,
+        job: &TranscodeJob,
+        mut passes: Vec<Vec<OsString>>,
+        dry_run: bool,
+        events: &dyn Events,
+    ) -> Result<(), failure::Error> {
+        let duration = job.expected_duration()?;
+        // Fails early if the job can't fit its target size.
+        job.video_bitrate()?;
+
+        let format = job.format();
+        let argv = passes.pop().unwrap_or_default();
+
+        let plan = Plan {
+            input: job.input(),
+            output: job.output(),
+            format: format.to_string(),
+            backend: self.backend.to_string(),
+            target: job.target(),
+            duration,
+            first_passes: passes,
+            argv,
+            dry_run,
+        };
+
+        events.plan(&plan);
+
+        if let Some(streams) = job.streams() {
+            for warning in &streams.warnings {
+                events.warning(job.input(), warning);
+            }
+        }
+
+        let passthrough = job.passthrough().cloned().collect::<Vec<_>>();
+
+        for option in passthrough::conflicts(&plan.argv, &passthrough) {
+            events.warning(
+                job.input(),
+                &format!(
+                    "passed through `{}` overrides an option set by format {}, prefer `--set` if \
+                     it is a setting of the format",
+                    option, format
+                ),
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Plan a job without running anything.
+    ///
+    /// The planned commands write straight to the output and print the regular stats of ffmpeg,
+    /// so that they can be run by hand.
+    pub fn dry_run(&self, job: &TranscodeJob, events: &dyn Events) -> Result<(), failure::Error> {
+        job.check_output()?;
+
+        if job.quality_goal().is_some() {
+            self.crf_range(job)?;
+
+            events.warning(
+                job.input(),
+                "the CRF reaching the target quality is searched for when transcoding, the \
+                 planned command uses the CRF of the format",
+            );
+        }
+
+        let passes = job
+            .pass_argv()
+            .into_iter()
+            .map(|args| {
+                iter::once(OsString::from(&self.command))
+                    .chain(args)
+                    .collect()
+            })
+            .collect();
+
+        self.report_plan(job, passes, true, events)
+    }
+
+    /// Run a job, writing its output atomically.
+    ///
+    /// Multi-pass jobs run each pass in turn, and the statistics shared between them are removed
+    /// once done. Jobs with a target size which overshoot it are retried at a lower bitrate.
+    pub fn transcode(
+        &self,
+        job: &TranscodeJob,
+        events: &dyn Events,
+    ) -> Result<Outcome, TranscodeError> {
+        job.check_output()?;
+
+        let searched;
+
+        let job = match job.quality_goal() {
+            Some(target) => {
+                searched = self.search_crf(job, target, events)?;
+                &searched
+            }
+            None => job,
+        };
+
+        let input = job.input();
+        let output = job.output();
+        let temp = TempOutput::new(output)?;
+
+        // ffmpeg appends a suffix for each file it writes, like `-0.log`.
+        let log = if job.passes() > 1 {
+            let dir = TempDir::new()?;
+            let log = dir.join("passlog");
+            Some((dir, log))
+        } else {
+            None
+        };
+
+        let started = Instant::now();
+        let mut job = Cow::Borrowed(job);
+        let mut out_time = None;
+
+        for attempt in 1.. {
+            let passes = (1..=job.passes())
+                .map(|number| {
+                    let pass = log.as_ref().and_then(|(_, log)| job.pass(number, log));
+
+                    iter::once(OsString::from(&self.command))
+                        .chain(job.args(temp.path(), true, pass))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>();
+
+            if attempt == 1 {
+                self.report_plan(&job, passes.clone(), false, events)?;
+            }
+
+            let count = passes.len() as u32;
+
+            for (number, argv) in (1..).zip(&passes) {
+                let pass = Some((number, count)).filter(|_| count > 1);
+                out_time = self.run(argv, input, pass, events)?;
+            }
+
+            let (limit, bitrate) = match (job.size_limit(), job.video_bitrate()?) {
+                (Some(limit), Some(bitrate)) => (limit, bitrate),
+                _ => break,
+            };
+
+            let size = fs::metadata(temp.path())?.len();
+
+            if size <= limit {
+                break;
+            }
+
+            if attempt == MAX_SIZE_ATTEMPTS {
+                return Err(TranscodeError::Other(format_err!(
+                    "output is {}, which is still over the target size of {} after {} attempts",
+                    size::format(size),
+                    size::format(limit),
+                    attempt
+                )));
+            }
+
+            // Aim a bit below the target, since the overshoot is rarely proportional.
+            let retry = (bitrate as f64 * limit as f64 / size as f64 * 0.95) as u64;
+            let retry = size::check_video_bitrate(limit, retry)?;
+
+            events.warning(
+                input,
+                &format!(
+                    "output is {}, which is over the target size of {}, retrying at {}k",
+                    size::format(size),
+                    size::format(limit),
+                    retry / 1000
+                ),
+            );
+
+            job = Cow::Owned(job.into_owned().fit_bitrate(retry));
+        }
+
+        temp.persist()?;
+
+        Ok(Outcome {
+            input: input.to_owned(),
+            output: output.to_owned(),
+            size: fs::metadata(output).map(|m| m.len()).unwrap_or_default(),
+            duration: out_time,
+            elapsed: started.elapsed().as_secs_f64(),
+        })
+    }
+
+    /// Search for the CRF which reaches the target quality by encoding and scoring samples, and
+    /// return the job encoding at it.
+    fn search_crf(
+        &self,
+        job: &TranscodeJob,
+        target: f64,
+        events: &dyn Events,
+    ) -> Result<TranscodeJob, TranscodeError> {
+        let input = job.input();
+        let range = self.crf_range(job)?;
+
+        let duration = job.expected_duration()?.ok_or_else(|| {
+            format_err!("the duration of the output must be known to reach a target quality")
+        })?;
+
+        let metric = self.metric()?;
+
+        if metric == Metric::Ssim {
+            events.warning(
+                input,
+                "ffmpeg doesn't have libvmaf, so samples are scored with SSIM scaled to 0-100 \
+                 instead, which is more lenient than VMAF",
+            );
+        }
+
+        let dir = TempDir::new()?;
+        let samples = quality::samples(duration);
+
+        let found = quality::search(range, target, |crf| {
+            let mut total = 0.0;
+
+            for (index, &(offset, length)) in samples.iter().enumerate() {
+                let sample = dir.join(&format!("sample-{}.mkv", index));
+
+                let encode = iter::once(OsString::from(&self.command))
+                    .chain(job.sample_args(offset, length, crf, &sample)?)
+                    .collect::<Vec<_>>();
+
+                self.sample(&encode)?;
+
+                let score = iter::once(OsString::from(&self.command))
+                    .chain(job.score_args(&sample, offset, length, metric)?)
+                    .collect::<Vec<_>>();
+
+                let stderr = self.sample(&score)?;
+
+                total += metric.parse(&stderr).ok_or_else(|| {
+                    format_err!("ffmpeg didn't print a {} score for a sample", metric)
+                })?;
+            }
+
+            let score = total / samples.len() as f64;
+            events.quality(input, crf, score, metric);
+            Ok::<_, TranscodeError>(score)
+        })?;
+
+        if !found.reached {
+            events.warning(
+                input,
+                &format!(
+                    "no CRF reaches a {} score of {}, using the best quality at crf {} ({:.2})",
+                    metric, target, found.crf, found.score
+                ),
+            );
+        }
+
+        Ok(job.clone().crf(found.crf))
+    }
+
+    /// The range of CRF values to search for the target quality of a job.
+    ///
+    /// Fails if the job can't be searched, or if ffmpeg has no filter to score samples with.
+    fn crf_range(&self, job: &TranscodeJob) -> Result<(u32, u32), failure::Error> {
+        let range = job.crf_range()?;
+        self.metric()?;
+        Ok(range)
+    }
+
+    /// The metric samples are scored with, VMAF if ffmpeg was built with libvmaf and SSIM
+    /// otherwise.
+    fn metric(&self) -> Result<Metric, failure::Error> {
+        match Metric::detect(&self.capabilities) {
+            Some(metric) => Ok(metric),
+            None => bail!(
+                "a target quality needs an ffmpeg with the libvmaf or ssim filter, which {} \
+                 doesn't have",
+                self.command.display()
+            ),
+        }
+    }
+
+    /// Run a short ffmpeg process for a sample to completion, returning the last lines it
+    /// printed to stderr.
+    fn sample(&self, argv: &[OsString]) -> Result<String, TranscodeError> {
+        let lines = self.execute(argv, |_| {})?;
+        Ok(lines.iter().collect::<Vec<_>>().join("\n"))
+    }
+
+    /// Run a single ffmpeg process to completion, reporting its progress and warnings.
+    ///
+    /// `pass` is the pass of a multi-pass encode the process runs, and how many passes there are.
+    /// Returns the last position in the output that ffmpeg reported.
+    fn run(
+        &self,
+        argv: &[OsString],
+        input: &Path,
+        pass: Option<(u32, u32)>,
+        events: &dyn Events,
+    ) -> Result<Option<f64>, TranscodeError> {
+        let mut out_time = None;
+
+        let lines = self.execute(argv, |mut progress| {
+            progress.pass = pass;
+            out_time = progress.out_time.or(out_time);
+            events.progress(&progress);
+        })?;
+
+        // Whatever ffmpeg printed is only reported as warnings once it succeeded, since the
+        // lines are part of the error otherwise.
+        for line in lines.iter() {
+            events.warning(input, line);
+        }
+
+        Ok(out_time)
+    }
+
+    /// Spawn a single ffmpeg process and wait for it to exit, passing the progress it reports to
+    /// `progress`.
+    ///
+    /// The process is stopped if the job is cancelled. Returns the last lines it printed to
+    /// stderr once it succeeded.
+    fn execute(
+        &self,
+        argv: &[OsString],
+        mut progress: impl FnMut(Progress),
+    ) -> Result<Stderr, TranscodeError> {
+        if self.cancel.is_cancelled() {
+            return Err(TranscodeError::Interrupted);
+        }
+
+        let Spawned {
+            stdout,
+            stderr,
+            mut process,
+        } = self.runner.spawn(argv)?;
+
+        let (tx, rx) = mpsc::channel();
+
+        let stdout_tx = tx.clone();
+
+        let stdout_thread = thread::spawn(move || {
+            let mut parser = progress::Parser::default();
+
+            for line in BufReader::new(stdout).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+
+                if let Some(progress) = parser.feed(&line) {
+                    if stdout_tx.send(Message::Progress(progress)).is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let stderr_thread = thread::spawn(move || {
+            for line in BufReader::new(stderr).lines() {
+                let line = match line {
+                    Ok(line) => line,
+                    Err(_) => break,
+                };
+
+                if tx.send(Message::Stderr(line)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut lines = Stderr::default();
+
+        // Ends once both threads are done and have dropped their senders.
+        loop {
+            match rx.recv_timeout(Duration::from_millis(100)) {
+                Ok(Message::Progress(p)) => progress(p),
+                Ok(Message::Stderr(line)) => {
+                    let line = line.trim();
+
+                    if !line.is_empty() {
+                        lines.push(line);
+                    }
+                }
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+
+            if self.cancel.is_cancelled() {
+                let _ = process.stop();
+                let _ = stdout_thread.join();
+                let _ = stderr_thread.join();
+                return Err(TranscodeError::Interrupted);
+            }
+        }
+
+        let _ = stdout_thread.join();
+        let _ = stderr_thread.join();
+
+        let status = process.wait()?;
+
+        if self.cancel.is_cancelled() {
+            return Err(TranscodeError::Interrupted);
+        }
+
+        if !status.success() {
+            return Err(TranscodeError::failed(status, &lines));
+        }
+
+        Ok(lines)
+    }
+}
+
+/// Messages sent from the threads reading the output of ffmpeg.
+enum Message {
+    Progress(Progress),
+    Stderr(String),
+}
@@ -0,0 +1,396 @@
+use crate::{
+    programs::FFPROBE_ENV,
+    runner::{Runner, System},
+};
+use failure::{bail, format_err};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    ffi::OsString,
+    fmt,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+/// ffprobe abstraction.
+pub struct Ffprobe {
+    runner: Arc<dyn Runner>,
+    command: PathBuf,
+}
+
+impl Ffprobe {
+    /// Create a new ffprobe abstraction testing that the given command works.
+    pub fn new(command: impl Into<PathBuf>) -> Result<Ffprobe, failure::Error> {
+        Self::with_runner(Arc::new(System), command)
+    }
+
+    /// Create a new ffprobe abstraction which runs the given command through the given runner.
+    pub fn with_runner(
+        runner: Arc<dyn Runner>,
+        command: impl Into<PathBuf>,
+    ) -> Result<Ffprobe, failure::Error> {
+        let command = command.into();
+
+        let o = runner
+            .output(&[command.clone().into(), "-version".into()])
+            .map_err(|e| {
+                format_err!(
+                    "could not run ffprobe: {}: {} (its location can be set with --ffprobe or {})",
+                    command.display(),
+                    e,
+                    FFPROBE_ENV
+                )
+            })?;
+
+        if !o.status.success() {
+            bail!("could not run: `{} -version`: {:?}", command.display(), o);
+        }
+
+        Ok(Ffprobe { runner, command })
+    }
+
+    /// Probe the given file for information on its container, streams and chapters.
+    pub fn probe(&self, input: impl AsRef<Path>) -> Result<MediaInfo, failure::Error> {
+        let input = input.as_ref();
+
+        let mut argv = vec![OsString::from(&self.command)];
+
+        argv.extend(
+            ["-v", "error", "-print_format", "json"]
+                .iter()
+                .chain(&["-show_streams", "-show_format", "-show_chapters"])
+                .map(OsString::from),
+        );
+
+        argv.push(input.into());
+
+        let o = self.runner.output(&argv)?;
+
+        if !o.status.success() {
+            bail!(
+                "failed to probe: {}: {}",
+                input.display(),
+                String::from_utf8_lossy(&o.stderr).trim()
+            );
+        }
+
+        MediaInfo::from_json(&o.stdout)
+            .map_err(|e| format_err!("bad ffprobe output for {}: {}", input.display(), e))
+    }
+}
+
+/// Information about a media file.
+#[derive(Debug, Clone)]
+pub struct MediaInfo {
+    /// Information about the container.
+    pub container: Container,
+    /// All streams in the file.
+    pub streams: Vec<Stream>,
+    /// All chapters in the file.
+    pub chapters: Vec<Chapter>,
+}
+
+impl MediaInfo {
+    /// Parse media information from the JSON output of ffprobe.
+    pub fn from_json(json: &[u8]) -> Result<MediaInfo, failure::Error> {
+        let raw: raw::Output = serde_json::from_slice(json)?;
+
+        let container = Container {
+            format: raw.format.format_name,
+            duration: parse(raw.format.duration),
+            bit_rate: parse(raw.format.bit_rate),
+            size: parse(raw.format.size),
+        };
+
+        let streams = raw.streams.into_iter().map(Stream::from_raw).collect();
+
+        let chapters = raw
+            .chapters
+            .into_iter()
+            .map(|c| Chapter {
+                start: parse(c.start_time).unwrap_or_default(),
+                end: parse(c.end_time).unwrap_or_default(),
+                title: c.tags.get("title").cloned(),
+            })
+            .collect();
+
+        Ok(MediaInfo {
+            container,
+            streams,
+            chapters,
+        })
+    }
+
+    /// The primary video stream, skipping attached pictures like cover art.
+    pub fn video(&self) -> Option<&Stream> {
+        self.streams
+            .iter()
+            .find(|s| s.kind == StreamKind::Video && !s.attached_pic)
+    }
+
+    /// The duration of the media in seconds, if known.
+    pub fn duration(&self) -> Option<f64> {
+        self.container.duration.or_else(|| {
+            self.streams
+                .iter()
+                .filter_map(|s| s.duration)
+                .fold(None, |a, d| Some(a.map_or(d, |a: f64| a.max(d))))
+        })
+    }
+}
+
+/// Information about the container of a media file.
+#[derive(Debug, Clone)]
+pub struct Container {
+    /// The name of the format, like `mov,mp4,m4a,3gp,3g2,mj2`.
+    pub format: Option<String>,
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+    /// Overall bit rate in bits per second.
+    pub bit_rate: Option<u64>,
+    /// Size of the file in bytes.
+    pub size: Option<u64>,
+}
+
+/// The kind of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StreamKind {
+    /// A video stream.
+    Video,
+    /// An audio stream.
+    Audio,
+    /// A subtitle stream.
+    Subtitle,
+    /// A data stream, like timecodes.
+    Data,
+    /// An attachment, like a font.
+    Attachment,
+    /// A stream ffprobe reported with a type that isn't known.
+    Unknown,
+}
+
+impl fmt::Display for StreamKind {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StreamKind::Video => "video".fmt(fmt),
+            StreamKind::Audio => "audio".fmt(fmt),
+            StreamKind::Subtitle => "subtitle".fmt(fmt),
+            StreamKind::Data => "data".fmt(fmt),
+            StreamKind::Attachment => "attachment".fmt(fmt),
+            StreamKind::Unknown => "unknown".fmt(fmt),
+        }
+    }
+}
+
+/// A single stream in a media file.
+#[derive(Debug, Clone)]
+pub struct Stream {
+    /// Index of the stream in the file.
+    pub index: usize,
+    /// The kind of the stream.
+    pub kind: StreamKind,
+    /// The codec of the stream, like `h264`.
+    pub codec: Option<String>,
+    /// Width of a video stream.
+    pub width: Option<u32>,
+    /// Height of a video stream.
+    pub height: Option<u32>,
+    /// Frame rate of a video stream.
+    pub frame_rate: Option<Rational>,
+    /// Pixel format of a video stream.
+    pub pix_fmt: Option<String>,
+    /// Number of channels in an audio stream.
+    pub channels: Option<u32>,
+    /// Sample rate of an audio stream.
+    pub sample_rate: Option<u32>,
+    /// Duration in seconds.
+    pub duration: Option<f64>,
+    /// Bit rate in bits per second.
+    pub bit_rate: Option<u64>,
+    /// Language of the stream.
+    pub language: Option<String>,
+    /// Title of the stream.
+    pub title: Option<String>,
+    /// If the stream is an attached picture, like cover art.
+    pub attached_pic: bool,
+}
+
+impl Stream {
+    /// The number of bits per component of a video stream, based on its pixel format.
+    ///
+    /// Returns `None` for pixel formats which aren't recognized.
+    pub fn bit_depth(&self) -> Option<u32> {
+        let pix_fmt = self.pix_fmt.as_deref()?;
+        let name = pix_fmt
+            .strip_suffix("le")
+            .or_else(|| pix_fmt.strip_suffix("be"))
+            .unwrap_or(pix_fmt);
+
+        let prefix = name.trim_end_matches(|c: char| c.is_ascii_digit());
+        let digits = &name[prefix.len()..];
+
+        match (prefix, digits) {
+            // Like `yuv420p` and `gbrp`.
+            (_, "") if prefix.ends_with('p') => Some(8),
+            ("nv", "12") | ("nv", "16") | ("nv", "21") | ("nv", "24") => Some(8),
+            // Like `yuv420p10le` and `gray12le`.
+            (prefix, digits) if prefix.ends_with('p') || prefix == "gray" => digits.parse().ok(),
+            // Semi-planar and packed formats like `p010le` and `y210le`.
+            ("p", digits) | ("y", digits) => digits.parse::<u32>().ok().map(|d| d % 100),
+            ("x2rgb", digits) | ("x2bgr", digits) => digits.parse().ok(),
+            _ => None,
+        }
+    }
+
+    fn from_raw(s: raw::Stream) -> Stream {
+        let kind = match s.codec_type.as_deref() {
+            Some("video") => StreamKind::Video,
+            Some("audio") => StreamKind::Audio,
+            Some("subtitle") => StreamKind::Subtitle,
+            Some("data") => StreamKind::Data,
+            Some("attachment") => StreamKind::Attachment,
+            _ => StreamKind::Unknown,
+        };
+
+        // avg_frame_rate is the actual rate for variable frame rate sources, while r_frame_rate
+        // is the base rate which can be much higher.
+        let frame_rate = [s.avg_frame_rate, s.r_frame_rate]
+            .iter()
+            .flatten()
+            .filter_map(|r| r.parse::<Rational>().ok())
+            .find(|r| r.num > 0 && r.den > 0);
+
+        Stream {
+            index: s.index,
+            kind,
+            codec: s.codec_name,
+            width: s.width,
+            height: s.height,
+            frame_rate,
+            pix_fmt: s.pix_fmt,
+            channels: s.channels,
+            sample_rate: parse(s.sample_rate),
+            duration: parse(s.duration),
+            bit_rate: parse(s.bit_rate),
+            language: s.tags.get("language").cloned(),
+            title: s.tags.get("title").cloned(),
+            attached_pic: s
+                .disposition
+                .get("attached_pic")
+                .cloned()
+                .unwrap_or_default()
+                != 0,
+        }
+    }
+}
+
+/// A single chapter in a media file.
+#[derive(Debug, Clone)]
+pub struct Chapter {
+    /// Start of the chapter in seconds.
+    pub start: f64,
+    /// End of the chapter in seconds.
+    pub end: f64,
+    /// Title of the chapter.
+    pub title: Option<String>,
+}
+
+/// A rational number, as used by ffprobe for frame rates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rational {
+    /// The numerator.
+    pub num: u32,
+    /// The denominator.
+    pub den: u32,
+}
+
+impl Rational {
+    /// Convert into a floating point value.
+    pub fn as_f64(self) -> f64 {
+        f64::from(self.num) / f64::from(self.den)
+    }
+}
+
+impl std::str::FromStr for Rational {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut it = s.splitn(2, '/');
+
+        let num = it.next().unwrap_or_default().parse()?;
+
+        let den = match it.next() {
+            Some(den) => den.parse()?,
+            None => 1,
+        };
+
+        Ok(Rational { num, den })
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        if self.den == 1 {
+            return self.num.fmt(fmt);
+        }
+
+        let value = (self.as_f64() * 100.0).round() / 100.0;
+        value.fmt(fmt)
+    }
+}
+
+/// ffprobe formats most numbers as strings, so parse them leniently.
+fn parse<T: std::str::FromStr>(value: Option<String>) -> Option<T> {
+    value.and_then(|v| v.parse().ok())
+}
+
+/// The JSON structure printed by ffprobe.
+mod raw {
+    use super::*;
+
+    #[derive(Deserialize)]
+    pub struct Output {
+        #[serde(default)]
+        pub streams: Vec<Stream>,
+        #[serde(default)]
+        pub format: Format,
+        #[serde(default)]
+        pub chapters: Vec<Chapter>,
+    }
+
+    #[derive(Default, Deserialize)]
+    pub struct Format {
+        pub format_name: Option<String>,
+        pub duration: Option<String>,
+        pub bit_rate: Option<String>,
+        pub size: Option<String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Stream {
+        pub index: usize,
+        pub codec_name: Option<String>,
+        pub codec_type: Option<String>,
+        pub width: Option<u32>,
+        pub height: Option<u32>,
+        pub pix_fmt: Option<String>,
+        pub r_frame_rate: Option<String>,
+        pub avg_frame_rate: Option<String>,
+        pub channels: Option<u32>,
+        pub sample_rate: Option<String>,
+        pub duration: Option<String>,
+        pub bit_rate: Option<String>,
+        #[serde(default)]
+        pub disposition: HashMap<String, u32>,
+        #[serde(default)]
+        pub tags: HashMap<String, String>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Chapter {
+        pub start_time: Option<String>,
+        pub end_time: Option<String>,
+        #[serde(default)]
+        pub tags: HashMap<String, String>,
+    }
+}
@@ -0,0 +1,738 @@
+//! The formats to transcode to, either built in or defined as presets.
+
+use crate::{
+    backend::{Backend, VAAPI_DEVICE},
+    capabilities::{Capabilities, Requirements},
+    config::Config,
+    ffprobe::{MediaInfo, Rational, StreamKind},
+    settings::{non_empty, Audio, Settings, Subtitle, Video},
+    target::{Bounds, Limits, VideoTarget},
+};
+use failure::{bail, format_err};
+use std::{fmt, path::Path, process};
+
+/// A built-in format.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Builtin {
+    /// YouTube-optimized format (up to 1080p @ 60fps)
+    YouTube,
+    /// High-quality GIF.
+    Gif,
+    /// Copy input parameters.
+    Copy,
+    /// Web-embeddable VP9 and Opus in WebM (up to 1080p @ 60fps).
+    WebM,
+    /// AV1 and Opus in Matroska (up to 1080p @ 60fps).
+    Av1,
+    /// HEVC at the source resolution and frame rate, keeping all audio and subtitle tracks.
+    Archive,
+}
+
+impl Builtin {
+    /// All built-in formats.
+    pub const ALL: &'static [Builtin] = &[
+        Builtin::YouTube,
+        Builtin::Gif,
+        Builtin::Copy,
+        Builtin::WebM,
+        Builtin::Av1,
+        Builtin::Archive,
+    ];
+
+    /// Names of the built-in formats.
+    pub const NAMES: &'static [&'static str] =
+        &["YouTube", "Gif", "Copy", "WebM", "Av1", "Archive"];
+
+    /// Find a built-in format by name, ignoring case.
+    pub fn find(name: &str) -> Option<Builtin> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|b| b.name().eq_ignore_ascii_case(name))
+    }
+
+    /// The name of the format.
+    pub fn name(self) -> &'static str {
+        match self {
+            Builtin::YouTube => "YouTube",
+            Builtin::Gif => "Gif",
+            Builtin::Copy => "Copy",
+            Builtin::WebM => "WebM",
+            Builtin::Av1 => "Av1",
+            Builtin::Archive => "Archive",
+        }
+    }
+
+    /// The maximum resolution and frame rate of the format, if it re-encodes video.
+    fn limits(self) -> Option<Limits> {
+        match self {
+            Builtin::YouTube | Builtin::WebM | Builtin::Av1 => Some(Limits {
+                bounds: Bounds::Edges(1920, 1080),
+                fps: Rational { num: 60, den: 1 },
+            }),
+            Builtin::Gif => Some(Limits {
+                bounds: Bounds::Width(280),
+                fps: Rational { num: 12, den: 1 },
+            }),
+            Builtin::Copy | Builtin::Archive => None,
+        }
+    }
+
+    /// The settings of the format with the given backend.
+    fn settings(self, backend: Backend) -> Settings {
+        let set = |value: &str| Some(value.to_string());
+
+        match self {
+            Builtin::YouTube => {
+                let video = match backend {
+                    Backend::Nvidia => Video {
+                        codec: set("h264_nvenc"),
+                        coder: set("1"),
+                        preset: set("llhq"),
+                        rc: set("vbr_minqp"),
+                        qmin: set("21"),
+                        qmax: set("23"),
+                        bitrate: set("5000k"),
+                        maxrate: set("8000k"),
+                        profile: set("high"),
+                        bframes: set("2"),
+                        ..Video::default()
+                    },
+                    Backend::Vaapi => Video {
+                        codec: set("h264_vaapi"),
+                        bitrate: set("5000k"),
+                        maxrate: set("8000k"),
+                        profile: set("high"),
+                        bframes: set("2"),
+                        ..Video::default()
+                    },
+                    Backend::Qsv => Video {
+                        codec: set("h264_qsv"),
+                        preset: set("slow"),
+                        bitrate: set("5000k"),
+                        maxrate: set("8000k"),
+                        profile: set("high"),
+                        bframes: set("2"),
+                        ..Video::default()
+                    },
+                    // crf sits in the middle of the qmin/qmax range used for nvenc, with the same
+                    // peak bitrate enforced through the vbv.
+                    Backend::Software => Video {
+                        codec: set("libx264"),
+                        preset: set("slow"),
+                        crf: set("22"),
+                        maxrate: set("8000k"),
+                        bufsize: set("16000k"),
+                        profile: set("high"),
+                        pix_fmt: set("yuv420p"),
+                        bframes: set("2"),
+                        ..Video::default()
+                    },
+                };
+
+                Settings {
+                    container: set("mp4"),
+                    video,
+                    audio: Audio {
+                        codec: set("aac"),
+                        profile: set("aac_low"),
+                        bitrate: set("384k"),
+                        ..Audio::default()
+                    },
+                    ..Settings::default()
+                }
+            }
+            Builtin::Gif => Settings {
+                container: set("gif"),
+                ..Settings::default()
+            },
+            Builtin::Copy => Settings {
+                video: Video {
+                    codec: set("copy"),
+                    ..Video::default()
+                },
+                audio: Audio {
+                    codec: set("copy"),
+                    ..Audio::default()
+                },
+                ..Settings::default()
+            },
+            // Constant quality, since the bitrate is unconstrained.
+            Builtin::WebM => Settings {
+                container: set("webm"),
+                passes: set("2"),
+                video: Video {
+                    codec: set("libvpx-vp9"),
+                    deadline: set("good"),
+                    cpu_used: set("2"),
+                    row_mt: set("1"),
+                    crf: set("31"),
+                    bitrate: set("0"),
+                    pix_fmt: set("yuv420p"),
+                    ..Video::default()
+                },
+                audio: Audio {
+                    codec: set("libopus"),
+                    bitrate: set("128k"),
+                    ..Audio::default()
+                },
+                ..Settings::default()
+            },
+            Builtin::Av1 => Settings {
+                container: set("matroska"),
+                extension: set("mkv"),
+                video: Video {
+                    codec: set("libsvtav1"),
+                    preset: set("8"),
+                    crf: set("32"),
+                    pix_fmt: set("yuv420p"),
+                    ..Video::default()
+                },
+                audio: Audio {
+                    codec: set("libopus"),
+                    bitrate: set("128k"),
+                    ..Audio::default()
+                },
+                ..Settings::default()
+            },
+            // The pixel format is decided from the source, see `Format::source`.
+            Builtin::Archive => {
+                let video = match backend {
+                    Backend::Nvidia => Video {
+                        codec: set("hevc_nvenc"),
+                        preset: set("slow"),
+                        rc: set("vbr"),
+                        cq: set("24"),
+                        bitrate: set("0"),
+                        tag: set("hvc1"),
+                        ..Video::default()
+                    },
+                    _ => Video {
+                        codec: set("libx265"),
+                        preset: set("slow"),
+                        crf: set("22"),
+                        tag: set("hvc1"),
+                        ..Video::default()
+                    },
+                };
+
+                Settings {
+                    container: set("mp4"),
+                    video,
+                    audio: Audio {
+                        codec: set("copy"),
+                        ..Audio::default()
+                    },
+                    // Text subtitles are converted since mp4 only supports its own format.
+                    subtitle: Subtitle {
+                        codec: set("mov_text"),
+                    },
+                    map: Some(ARCHIVE_MAP.iter().map(|m| m.to_string()).collect()),
+                    ..Settings::default()
+                }
+            }
+        }
+    }
+}
+
+/// The streams mapped by Archive, unless it has decided how to handle each stream of the source.
+const ARCHIVE_MAP: &[&str] = &["0:v:0", "0:a?", "0:s?"];
+
+/// Audio codecs which can be copied into an mp4.
+const MP4_AUDIO: &[&str] = &["aac", "ac3", "alac", "eac3", "flac", "mp3", "opus"];
+
+/// Text subtitle codecs which can be converted to mov_text.
+const TEXT_SUBTITLES: &[&str] = &["ass", "mov_text", "ssa", "subrip", "text", "webvtt"];
+
+/// How the streams of a source are mapped and encoded, by formats which handle each stream on its
+/// own.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Streams {
+    /// The streams mapped into the output.
+    pub(crate) map: Vec<String>,
+    /// Encoder arguments of single output streams, which take priority over the settings.
+    pub(crate) args: Vec<String>,
+    /// Streams which are transcoded or dropped, and why.
+    pub(crate) warnings: Vec<String>,
+}
+
+impl Streams {
+    /// Decide how Archive handles each stream of the source.
+    ///
+    /// Audio which mp4 can't hold is transcoded to AAC instead of being copied, and subtitles
+    /// which can't be converted to mov_text, like bitmap subtitles, are dropped. Settings which
+    /// map or encode streams differently are left as they are.
+    fn archive(settings: &Settings, info: &MediaInfo) -> Option<Streams> {
+        let defaults = non_empty(&settings.audio.codec) == Some("copy")
+            && non_empty(&settings.subtitle.codec) == Some("mov_text")
+            && settings
+                .map
+                .as_deref()
+                .is_some_and(|map| map == ARCHIVE_MAP);
+
+        if !defaults {
+            return None;
+        }
+
+        let mut streams = Streams {
+            map: vec![String::from("0:v:0")],
+            ..Streams::default()
+        };
+
+        let mut audio = 0;
+
+        for stream in &info.streams {
+            let codec = stream.codec.as_deref().unwrap_or("unknown");
+
+            match stream.kind {
+                StreamKind::Audio => {
+                    streams.map.push(format!("0:{}", stream.index));
+
+                    if !MP4_AUDIO.contains(&codec) {
+                        streams.args.extend([
+                            format!("-c:a:{}", audio),
+                            String::from("aac"),
+                            format!("-b:a:{}", audio),
+                            String::from("384k"),
+                        ]);
+
+                        streams.warnings.push(format!(
+                            "audio stream 0:{} ({}) can't be copied into mp4, transcoding it to aac",
+                            stream.index, codec
+                        ));
+                    }
+
+                    audio += 1;
+                }
+                StreamKind::Subtitle if TEXT_SUBTITLES.contains(&codec) => {
+                    streams.map.push(format!("0:{}", stream.index));
+                }
+                StreamKind::Subtitle => {
+                    streams.warnings.push(format!(
+                        "subtitle stream 0:{} ({}) can't be converted for mp4, dropping it",
+                        stream.index, codec
+                    ));
+                }
+                _ => {}
+            }
+        }
+
+        Some(streams)
+    }
+}
+
+/// The format to transcode to.
+#[derive(Debug, Clone)]
+pub struct Format {
+    /// Name of the format.
+    name: String,
+    /// The built-in format this format extends, if any.
+    base: Option<Builtin>,
+    /// Whether the format is a preset.
+    preset: bool,
+    backend: Backend,
+    /// The hardware decoders of the backend which ffmpeg has, if they are known.
+    decoders: Option<Vec<&'static str>>,
+    /// The hardware decoder for the video of the source, if the backend has one for its codec.
+    decoder: Option<&'static str>,
+    /// How each stream of the source is handled, if the format decided it from the source.
+    streams: Option<Streams>,
+    /// The settings of the format, merged on top of those of the built-in format.
+    pub settings: Settings,
+}
+
+impl Format {
+    /// Construct a built-in format with its settings for the given backend.
+    pub fn builtin(builtin: Builtin, backend: Backend) -> Format {
+        Format {
+            name: builtin.name().to_string(),
+            base: Some(builtin),
+            preset: false,
+            backend,
+            decoders: None,
+            decoder: None,
+            streams: None,
+            settings: builtin.settings(backend),
+        }
+    }
+
+    /// Find a built-in format or a preset by name, and resolve its settings for the given
+    /// backend.
+    pub fn parse(name: &str, config: &Config, backend: Backend) -> Result<Format, failure::Error> {
+        if let Some(builtin) = Builtin::find(name) {
+            return Ok(Format::builtin(builtin, backend));
+        }
+
+        let (name, _) = match config.preset(name) {
+            Some(preset) => preset,
+            None => {
+                let available = Builtin::NAMES
+                    .iter()
+                    .copied()
+                    .chain(config.presets.keys().map(String::as_str))
+                    .collect::<Vec<_>>();
+
+                bail!(
+                    "illegal --format: {} (available: {})",
+                    name,
+                    available.join(", ")
+                );
+            }
+        };
+
+        // Presets from the one being used up to the furthest one it extends.
+        let mut chain = Vec::new();
+        let mut current = name;
+
+        let base = loop {
+            let (key, preset) = match config.preset(current) {
+                Some(preset) => preset,
+                None => match Builtin::find(current) {
+                    Some(builtin) => break Some(builtin),
+                    None => bail!(
+                        "invalid config: {}.extends: no format named `{}`",
+                        config.location(chain.last().map(|(key, _)| *key).unwrap_or(name)),
+                        current
+                    ),
+                },
+            };
+
+            if chain.iter().any(|(k, _)| *k == key) {
+                bail!(
+                    "invalid config: {}.extends: extending `{}` forms a cycle",
+                    config.location(chain.last().map(|(key, _)| *key).unwrap_or(name)),
+                    key
+                );
+            }
+
+            chain.push((key, preset));
+
+            match preset.extends.as_deref() {
+                Some(extends) => current = extends,
+                None => break None,
+            }
+        };
+
+        let mut settings = base.map(|b| b.settings(backend)).unwrap_or_default();
+
+        for (_, preset) in chain.iter().rev() {
+            settings.merge(preset);
+        }
+
+        // Presets are valid on their own, but not necessarily once merged with what they extend.
+        settings.validate().map_err(|e| {
+            format_err!(
+                "invalid config: {} (merged with what it extends): {}",
+                config.location(name),
+                e
+            )
+        })?;
+
+        Ok(Format {
+            name: name.to_string(),
+            base,
+            preset: true,
+            backend,
+            decoders: None,
+            decoder: None,
+            streams: None,
+            settings,
+        })
+    }
+
+    /// Replace encoders which ffmpeg doesn't have with equivalent ones that it does.
+    ///
+    /// Av1 prefers libsvtav1, but falls back to libaom if that is all ffmpeg was built with, and
+    /// Archive falls back from hevc_nvenc to libx265. Sources are only decoded in hardware with
+    /// decoders that ffmpeg has.
+    pub fn adapt(&mut self, capabilities: &Capabilities) {
+        self.decoders = Some(
+            self.backend
+                .decoders()
+                .filter(|decoder| capabilities.has_decoder(decoder))
+                .collect(),
+        );
+
+        let video = &mut self.settings.video;
+
+        if non_empty(&video.codec) == Some("libsvtav1")
+            && !capabilities.has_encoder("libsvtav1")
+            && capabilities.has_encoder("libaom-av1")
+        {
+            let set = |value: &str| Some(value.to_string());
+            video.codec = set("libaom-av1");
+            // Presets of libsvtav1 mean nothing to libaom, which is tuned through cpu-used.
+            video.preset = None;
+            video.cpu_used = set("4");
+            video.row_mt = set("1");
+            // Constant quality.
+            video.bitrate = set("0");
+        }
+
+        if self.base == Some(Builtin::Archive)
+            && non_empty(&video.codec) == Some("hevc_nvenc")
+            && !capabilities.has_encoder("hevc_nvenc")
+            && capabilities.has_encoder("libx265")
+        {
+            *video = Builtin::Archive.settings(Backend::Software).video;
+        }
+    }
+
+    /// Adjust the settings to the source as probed by ffprobe.
+    ///
+    /// The hardware decoder is picked from the codec of the source, which is otherwise decoded in
+    /// software. Decoders which ffmpeg doesn't have are skipped if the format has been adapted to
+    /// it. Archive decides how to handle each stream of the source in an mp4, and keeps
+    /// sources with a high bit depth at 10 bits unless a pixel format has been set.
+    pub fn source(&mut self, info: &MediaInfo) {
+        let backend = self.backend;
+        let decoders = self.decoders.as_deref();
+
+        self.decoder = info
+            .video()
+            .and_then(|v| v.codec.as_deref())
+            .and_then(|codec| backend.decoder(codec))
+            .filter(|decoder| decoders.is_none_or(|d| d.contains(decoder)));
+
+        if self.base != Some(Builtin::Archive) {
+            return;
+        }
+
+        self.streams = Streams::archive(&self.settings, info);
+
+        if self.settings.video.pix_fmt.is_some() {
+            return;
+        }
+
+        let high = info
+            .video()
+            .and_then(|v| v.bit_depth())
+            .is_some_and(|depth| depth > 8);
+
+        let nvenc = non_empty(&self.settings.video.codec) == Some("hevc_nvenc");
+
+        let pix_fmt = match (high, nvenc) {
+            (true, true) => "p010le",
+            (true, _) => "yuv420p10le",
+            (false, _) => "yuv420p",
+        };
+
+        self.settings.video.pix_fmt = Some(pix_fmt.to_string());
+    }
+
+    /// How each stream of the source is handled, if the format decided it from the source.
+    pub(crate) fn streams(&self) -> Option<&Streams> {
+        self.streams.as_ref()
+    }
+
+    /// How many passes the video is encoded in.
+    ///
+    /// Settings are validated when they are set, so an invalid number falls back to one pass.
+    pub fn passes(&self) -> u32 {
+        self.settings.passes().unwrap_or(1)
+    }
+
+    /// The maximum resolution and frame rate of the format, if it re-encodes video.
+    pub fn limits(&self) -> Option<Limits> {
+        self.base.and_then(Builtin::limits)
+    }
+
+    /// Decide the video target for this format based on the source.
+    pub fn target(&self, info: &MediaInfo) -> Option<VideoTarget> {
+        self.limits().map(|limits| VideoTarget::new(limits, info))
+    }
+
+    /// Test if the format uses a hardware encoder.
+    pub fn is_hardware(&self) -> bool {
+        self.settings.is_hardware()
+    }
+
+    /// The capabilities of ffmpeg that this format needs.
+    pub fn requirements(&self) -> Requirements<'_> {
+        let mut r = Requirements::default();
+        let backend = self.backend;
+
+        match self.base {
+            Some(Builtin::YouTube) => {
+                match backend {
+                    Backend::Nvidia | Backend::Software => {}
+                    Backend::Vaapi => {
+                        r.hwaccels.push("vaapi");
+                        r.filters.extend(&["format", "hwupload"]);
+                    }
+                    Backend::Qsv => {
+                        r.hwaccels.push("qsv");
+                    }
+                }
+
+                r.decoders.extend(self.decoder);
+
+                r.filters.extend(&[backend.scale_filter(), "fps"]);
+            }
+            Some(Builtin::Gif) => {
+                r.encoders.push("gif");
+                r.filters
+                    .extend(&["fps", "scale", "split", "palettegen", "paletteuse"]);
+            }
+            Some(Builtin::WebM) | Some(Builtin::Av1) => {
+                r.filters.extend(&["scale", "fps"]);
+            }
+            Some(Builtin::Copy) | Some(Builtin::Archive) | None => {}
+        }
+
+        r.encoders.extend(self.settings.encoders());
+        r
+    }
+
+    /// Add the arguments of the format which go before the input, like hardware decoding.
+    pub fn input_args(&self, cmd: &mut process::Command) {
+        if let Some(Builtin::YouTube) = self.base {
+            match self.backend {
+                Backend::Nvidia => {
+                    if let Some(decoder) = self.decoder {
+                        cmd.args(["-hwaccel", "cuvid", "-c:v", decoder]);
+                    }
+                }
+                Backend::Vaapi => {
+                    cmd.args(["-hwaccel", "vaapi", "-hwaccel_device", VAAPI_DEVICE]);
+                    cmd.args(["-hwaccel_output_format", "vaapi"]);
+                }
+                Backend::Qsv => {
+                    if let Some(decoder) = self.decoder {
+                        cmd.args(["-hwaccel", "qsv", "-c:v", decoder]);
+                    }
+                }
+                Backend::Software => {}
+            }
+        }
+
+        if let Some(args) = self.settings.input_args.as_ref() {
+            cmd.args(args);
+        }
+    }
+
+    /// The extension of the output, based on the input.
+    pub fn extension(&self, input: &Path) -> Result<String, failure::Error> {
+        let settings = &self.settings;
+
+        if let Some(ext) = non_empty(&settings.extension).or(non_empty(&settings.container)) {
+            return Ok(ext.to_string());
+        }
+
+        match input.extension().and_then(|s| s.to_str()) {
+            Some(ext) => Ok(ext.to_string()),
+            None => Err(format_err!("expected extension: {}", input.display())),
+        }
+    }
+
+    /// The template used to name outputs unless another one is specified.
+    pub fn default_template(&self) -> &'static str {
+        match self.base {
+            // The extension of a preset might be the same as the input's.
+            _ if self.preset => "{stem}.{format}.{ext}",
+            Some(Builtin::Copy) => "{stem}.copy.{ext}",
+            // Captures are often in the container of the archive.
+            Some(Builtin::Archive) => "{stem}.archive.{ext}",
+            _ => "{stem}.{ext}",
+        }
+    }
+
+    /// Add the arguments of the format which go after the input and the mapped streams, like
+    /// filters and encoder settings, scaling the video to `target` if there is one.
+    pub fn output_args(&self, target: Option<&VideoTarget>, cmd: &mut process::Command) {
+        let settings = &self.settings;
+        let backend = self.backend;
+
+        if let Some(filter_complex) = settings.filter_complex.as_ref() {
+            cmd.arg("-filter_complex");
+            cmd.arg(filter_complex);
+        } else {
+            let mut filters = Vec::new();
+
+            if let Some(Builtin::YouTube) = self.base {
+                // Frames stay on the device if they were hardware decoded, otherwise they are
+                // uploaded before encoding.
+                if let Backend::Vaapi = backend {
+                    filters.push(String::from("format=nv12|vaapi"));
+                    filters.push(String::from("hwupload"));
+                }
+
+                // Frames which QSV couldn't decode are in system memory, where scale_qsv can't
+                // reach them.
+                let scaler = match (backend, self.decoder) {
+                    (Backend::Qsv, None) => Backend::Software,
+                    (backend, _) => backend,
+                };
+
+                if let Some(target) = target {
+                    if let Some((width, height)) = target.size {
+                        filters.push(scaler.scale(width, height));
+                    }
+
+                    filters.extend(target.fps_filter());
+                }
+            }
+
+            // Frames are always decoded and encoded in software.
+            if let Some(Builtin::WebM) | Some(Builtin::Av1) = self.base {
+                if let Some(target) = target {
+                    if let Some((width, height)) = target.size {
+                        filters.push(format!("scale={}:{}", width, height));
+                    }
+
+                    filters.extend(target.fps_filter());
+                }
+            }
+
+            if let Some(Builtin::Gif) = self.base {
+                if let Some(target) = target {
+                    filters.extend(target.fps_filter());
+
+                    if let Some((width, height)) = target.size {
+                        filters.push(format!("scale={}:{}", width, height));
+                    }
+                }
+            }
+
+            filters.extend(settings.video_filters.iter().flatten().cloned());
+
+            if let Some(Builtin::Gif) = self.base {
+                filters.push(String::from("split [a][b]"));
+
+                cmd.arg("-filter_complex");
+                cmd.arg(format!(
+                    "[0:v] {};[a] palettegen [p];[b][p] paletteuse",
+                    filters.join(",")
+                ));
+            } else if !filters.is_empty() {
+                cmd.arg("-vf");
+                cmd.arg(filters.join(","));
+            }
+
+            if let Some(audio_filters) = settings.audio_filters.as_ref() {
+                if !audio_filters.is_empty() {
+                    cmd.arg("-af");
+                    cmd.arg(audio_filters.join(","));
+                }
+            }
+        }
+
+        settings.video_args(cmd);
+        settings.audio_args(cmd);
+        settings.subtitle_args(cmd);
+
+        if let Some(args) = settings.output_args.as_ref() {
+            cmd.args(args);
+        }
+
+        if let Some(container) = non_empty(&settings.container) {
+            cmd.args(["-f", container]);
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        self.name.fmt(fmt)
+    }
+}
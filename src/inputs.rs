@@ -0,0 +1,167 @@
+//! Expansion of the inputs given on the command line into files to transcode.
+
+use failure::{bail, format_err};
+use std::{
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// Extensions picked up from directories when no explicit filter is given.
+const DEFAULT_EXTENSIONS: &[&str] = &[
+    "avi", "flv", "m2ts", "m4v", "mkv", "mov", "mp4", "mpeg", "mpg", "mts", "ts", "webm", "wmv",
+];
+
+/// How to expand inputs into files.
+#[derive(Debug, Default)]
+pub struct Inputs {
+    /// Descend into subdirectories of directory inputs.
+    pub recursive: bool,
+    /// Only pick up files with these extensions from directories and globs.
+    pub extensions: Vec<String>,
+}
+
+impl Inputs {
+    /// Expand the given inputs, which can be files, directories or glob patterns.
+    ///
+    /// Files are always included, while files found in directories and through globs are
+    /// filtered by extension. Temporary outputs left behind by an interrupted tessie are never
+    /// picked up from directories or globs.
+    pub fn expand<'a>(
+        &self,
+        inputs: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Vec<PathBuf>, failure::Error> {
+        let mut files = Vec::new();
+
+        for input in inputs {
+            let path = Path::new(input);
+
+            if path.is_file() {
+                files.push(path.to_owned());
+                continue;
+            }
+
+            if path.is_dir() {
+                self.walk(path, &mut files)?;
+                continue;
+            }
+
+            if is_pattern(input) {
+                let mut found = false;
+
+                for entry in glob::glob(input)? {
+                    let entry = entry?;
+
+                    if entry.is_file() && self.matches(&entry, false) && !is_temp_output(&entry) {
+                        files.push(entry);
+                        found = true;
+                    }
+                }
+
+                if !found {
+                    bail!("no files matching: {}", input);
+                }
+
+                continue;
+            }
+
+            bail!("no such file or directory: {}", input);
+        }
+
+        let mut seen = HashSet::new();
+        files.retain(|f| seen.insert(f.clone()));
+        Ok(files)
+    }
+
+    /// Collect matching files from a directory, in sorted order.
+    fn walk(&self, dir: &Path, files: &mut Vec<PathBuf>) -> Result<(), failure::Error> {
+        let mut entries = fs::read_dir(dir)
+            .map_err(|e| format_err!("failed to read directory: {}: {}", dir.display(), e))?
+            .map(|e| e.map(|e| e.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        entries.sort();
+
+        for path in entries {
+            if path.is_dir() {
+                if self.recursive {
+                    self.walk(&path, files)?;
+                }
+
+                continue;
+            }
+
+            if path.is_file() && self.matches(&path, true) && !is_temp_output(&path) {
+                files.push(path);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Test if the path has a matching extension.
+    ///
+    /// Without an explicit filter, `default` decides if the default media extensions are used or
+    /// if everything matches.
+    fn matches(&self, path: &Path, default: bool) -> bool {
+        let ext = match path.extension().and_then(|e| e.to_str()) {
+            Some(ext) => ext.to_lowercase(),
+            None => return self.extensions.is_empty() && !default,
+        };
+
+        if !self.extensions.is_empty() {
+            return self
+                .extensions
+                .iter()
+                .any(|e| e.trim_start_matches('.').eq_ignore_ascii_case(&ext));
+        }
+
+        !default || DEFAULT_EXTENSIONS.contains(&ext.as_str())
+    }
+}
+
+/// Test if the input looks like a glob pattern.
+fn is_pattern(input: &str) -> bool {
+    input.contains(['*', '?', '['])
+}
+
+/// Test if the path looks like the temporary file of an output, which might have been left behind
+/// by a tessie which was killed.
+///
+/// These are named like `.clip.tessie-1234.tmp.mp4`, next to the output they are written for.
+pub fn is_temp_output(path: &Path) -> bool {
+    let name = match path.file_name().and_then(|n| n.to_str()) {
+        Some(name) => name,
+        None => return false,
+    };
+
+    let rest = match name
+        .strip_prefix('.')
+        .and_then(|n| n.rsplit_once(".tessie-"))
+    {
+        Some((_, rest)) => rest,
+        None => return false,
+    };
+
+    match rest.split_once(".tmp") {
+        Some((pid, _)) => !pid.is_empty() && pid.chars().all(|c| c.is_ascii_digit()),
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_temp_output;
+    use std::path::Path;
+
+    #[test]
+    fn detects_temp_outputs() {
+        assert!(is_temp_output(Path::new(
+            "dir/.clip.copy.tessie-123.tmp.mkv"
+        )));
+        assert!(is_temp_output(Path::new(".clip.tessie-123.tmp")));
+        assert!(!is_temp_output(Path::new("clip.tessie-123.tmp.mp4")));
+        assert!(!is_temp_output(Path::new(".clip.tessie-abc.tmp.mp4")));
+        assert!(!is_temp_output(Path::new(".clip.mp4")));
+    }
+}
@@ -0,0 +1,600 @@
+//! A typed builder for a single transcode.
+
+use crate::{
+    ffprobe::{MediaInfo, StreamKind},
+    format::{Format, Streams},
+    quality::{self, Metric},
+    settings::non_empty,
+    size,
+    target::VideoTarget,
+    timestamp,
+};
+use failure::{bail, format_err};
+use std::{
+    borrow::Cow,
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// A single transcode from an input into an output with a given format.
+///
+/// ```no_run
+/// use tessie::{Builtin, Ffmpeg, Ffprobe, Format, TranscodeJob};
+///
+/// # fn main() -> Result<(), failure::Error> {
+/// let ffmpeg = Ffmpeg::new("ffmpeg", None)?;
+/// let info = Ffprobe::new("ffprobe")?.probe("clip.mkv")?;
+/// let format = Format::builtin(Builtin::YouTube, ffmpeg.backend());
+///
+/// let job = TranscodeJob::new(format, "clip.mkv", "clip.mp4")
+///     .source(&info)
+///     .start("00:01:00")
+///     .duration("30");
+///
+/// println!("{:?}", job.argv());
+/// # Ok(()) }
+/// ```
+#[derive(Debug, Clone)]
+pub struct TranscodeJob {
+    format: Format,
+    input: PathBuf,
+    output: PathBuf,
+    /// The video target decided from the source.
+    target: Option<VideoTarget>,
+    /// Duration of the source in seconds, if known.
+    source_duration: Option<f64>,
+    /// Combined bitrate of the audio streams of the source in bits per second, if known.
+    source_audio: Option<u64>,
+    /// The size in bytes the output must fit within.
+    target_size: Option<u64>,
+    /// The video bitrate used to fit the target size, replacing the one estimated from it.
+    fit_bitrate: Option<u64>,
+    /// The quality score the output should reach.
+    target_quality: Option<f64>,
+    /// The CRF found to reach the target quality.
+    crf: Option<u32>,
+    map: Vec<String>,
+    start: Option<String>,
+    end: Option<String>,
+    duration: Option<String>,
+    /// Arguments passed through before the input.
+    input_args: Vec<String>,
+    /// Arguments passed through after the output options of the format.
+    output_args: Vec<String>,
+    /// Arguments passed through right before the output path.
+    trailing_args: Vec<String>,
+}
+
+impl TranscodeJob {
+    /// Construct a job transcoding the input into the output with the given format.
+    pub fn new(format: Format, input: impl AsRef<Path>, output: impl AsRef<Path>) -> TranscodeJob {
+        TranscodeJob {
+            format,
+            input: input.as_ref().to_owned(),
+            output: output.as_ref().to_owned(),
+            target: None,
+            source_duration: None,
+            source_audio: None,
+            target_size: None,
+            fit_bitrate: None,
+            target_quality: None,
+            crf: None,
+            map: Vec::new(),
+            start: None,
+            end: None,
+            duration: None,
+            input_args: Vec::new(),
+            output_args: Vec::new(),
+            trailing_args: Vec::new(),
+        }
+    }
+
+    /// Describe the source as probed by ffprobe.
+    ///
+    /// This decides the resolution and frame rate of the output, and lets the duration of the
+    /// output be estimated. Without it, the source is transcoded at its own resolution and frame
+    /// rate.
+    pub fn source(mut self, info: &MediaInfo) -> TranscodeJob {
+        self.format.source(info);
+        self.target = self.format.target(info);
+        self.source_duration = info.duration();
+
+        let audio = info
+            .streams
+            .iter()
+            .filter(|s| s.kind == StreamKind::Audio)
+            .map(|s| s.bit_rate)
+            .collect::<Option<Vec<_>>>();
+
+        self.source_audio = audio.map(|rates| rates.iter().sum());
+        self
+    }
+
+    /// Fit the output within the given size in bytes.
+    ///
+    /// The video is encoded at the average bitrate which fills the size once the audio has been
+    /// accounted for, which needs the duration of the output to be known. Software encoders are
+    /// run in two passes to hit the bitrate accurately.
+    pub fn target_size(mut self, size: u64) -> TranscodeJob {
+        self.target_size = Some(size);
+        self
+    }
+
+    /// Encode the video at the given bitrate in bits per second instead of the one estimated to
+    /// fit the target size.
+    pub(crate) fn fit_bitrate(mut self, bitrate: u64) -> TranscodeJob {
+        self.fit_bitrate = Some(bitrate);
+        self
+    }
+
+    /// Encode the video at the highest CRF which reaches the given quality score, like `95`.
+    ///
+    /// The CRF is searched for by scoring short samples of the input with VMAF, or SSIM scaled
+    /// to 0-100 if ffmpeg wasn't built with libvmaf, before the output is transcoded.
+    pub fn target_quality(mut self, score: f64) -> TranscodeJob {
+        self.target_quality = Some(score);
+        self
+    }
+
+    /// Encode the video at the given CRF instead of the one of the format.
+    pub(crate) fn crf(mut self, crf: u32) -> TranscodeJob {
+        self.crf = Some(crf);
+        self
+    }
+
+    /// Map a track into the output, like `0:1`.
+    ///
+    /// Mapping any track replaces the tracks mapped by the format.
+    pub fn map(mut self, map: impl Into<String>) -> TranscodeJob {
+        self.map.push(map.into());
+        self
+    }
+
+    /// Transcode from the given timestamp.
+    pub fn start(mut self, start: impl Into<String>) -> TranscodeJob {
+        self.start = Some(start.into());
+        self
+    }
+
+    /// Stop transcoding at the given timestamp.
+    pub fn end(mut self, end: impl Into<String>) -> TranscodeJob {
+        self.end = Some(end.into());
+        self
+    }
+
+    /// Transcode for the given duration, which takes priority over the end.
+    pub fn duration(mut self, duration: impl Into<String>) -> TranscodeJob {
+        self.duration = Some(duration.into());
+        self
+    }
+
+    /// Pass an argument to ffmpeg before the input.
+    pub fn input_arg(mut self, arg: impl Into<String>) -> TranscodeJob {
+        self.input_args.push(arg.into());
+        self
+    }
+
+    /// Pass an argument to ffmpeg after the output options of the format.
+    pub fn output_arg(mut self, arg: impl Into<String>) -> TranscodeJob {
+        self.output_args.push(arg.into());
+        self
+    }
+
+    /// Pass an argument to ffmpeg right before the output path.
+    pub fn trailing_arg(mut self, arg: impl Into<String>) -> TranscodeJob {
+        self.trailing_args.push(arg.into());
+        self
+    }
+
+    /// The format of the job.
+    pub fn format(&self) -> &Format {
+        &self.format
+    }
+
+    /// The input of the job.
+    pub fn input(&self) -> &Path {
+        &self.input
+    }
+
+    /// The output of the job.
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+
+    /// Check that writing the output doesn't replace the input, even if it is reached through
+    /// another path.
+    pub fn check_output(&self) -> Result<(), failure::Error> {
+        if let (Ok(input), Ok(output)) = (self.input.canonicalize(), self.output.canonicalize()) {
+            if input == output {
+                bail!(
+                    "output is the same file as the input: {}",
+                    self.output.display()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The video target decided from the source, if the format re-encodes video and a source has
+    /// been described.
+    pub fn target(&self) -> Option<&VideoTarget> {
+        self.target.as_ref()
+    }
+
+    /// The size in bytes the output must fit within, if any.
+    pub fn size_limit(&self) -> Option<u64> {
+        self.target_size
+    }
+
+    /// The average video bitrate in bits per second needed to fit the target size, if one is set.
+    pub fn video_bitrate(&self) -> Result<Option<u64>, failure::Error> {
+        let size = match self.target_size {
+            Some(size) => size,
+            None => return Ok(None),
+        };
+
+        match non_empty(&self.format.settings.video.codec) {
+            Some("copy") | None => bail!(
+                "format {} doesn't encode video at a bitrate, so it can't fit a target size",
+                self.format
+            ),
+            Some(_) => {}
+        }
+
+        if let Some(bitrate) = self.fit_bitrate {
+            return Ok(Some(size::check_video_bitrate(size, bitrate)?));
+        }
+
+        let duration = self.expected_duration()?.ok_or_else(|| {
+            format_err!("the duration of the output must be known to fit a target size")
+        })?;
+
+        Ok(Some(size::video_bitrate(
+            size,
+            duration,
+            self.audio_bitrate(),
+        )?))
+    }
+
+    /// The quality score the output should reach, if any.
+    pub fn quality_goal(&self) -> Option<f64> {
+        self.target_quality
+    }
+
+    /// The range of CRF values to search to reach the target quality.
+    pub fn crf_range(&self) -> Result<(u32, u32), failure::Error> {
+        if self.target_size.is_some() {
+            bail!("a target quality can't be combined with a target size");
+        }
+
+        let video = &self.format.settings.video;
+
+        let codec = match (non_empty(&video.codec), non_empty(&video.crf)) {
+            (Some(codec), Some(_)) => codec,
+            _ => bail!(
+                "format {} doesn't encode video at a constant quality (video.crf), so it can't \
+                 reach a target quality",
+                self.format
+            ),
+        };
+
+        quality::crf_range(codec)
+            .ok_or_else(|| format_err!("can't search for the CRF of encoder `{}`", codec))
+    }
+
+    /// The estimated bitrate of the audio in the output in bits per second.
+    fn audio_bitrate(&self) -> u64 {
+        let audio = &self.format.settings.audio;
+
+        match non_empty(&audio.codec) {
+            Some("copy") => self.source_audio.unwrap_or(size::DEFAULT_AUDIO_BITRATE),
+            _ => non_empty(&audio.bitrate)
+                .and_then(size::parse_bitrate)
+                .unwrap_or(size::DEFAULT_AUDIO_BITRATE),
+        }
+    }
+
+    /// The format with its video settings adjusted to fit the target size or quality.
+    fn tuned_format(&self, crf: Option<u32>) -> Cow<'_, Format> {
+        if let Some(crf) = crf {
+            let mut format = self.format.clone();
+            format.settings.video.crf = Some(crf.to_string());
+            return Cow::Owned(format);
+        }
+
+        match self.video_bitrate() {
+            Ok(Some(bitrate)) => {
+                let mut format = self.format.clone();
+                format.settings.video.average_bitrate(bitrate);
+                Cow::Owned(format)
+            }
+            _ => Cow::Borrowed(&self.format),
+        }
+    }
+
+    /// How each stream of the source is handled, unless streams are mapped explicitly.
+    pub(crate) fn streams(&self) -> Option<&Streams> {
+        self.format.streams().filter(|_| self.map.is_empty())
+    }
+
+    /// All arguments passed through to ffmpeg.
+    pub fn passthrough(&self) -> impl Iterator<Item = &String> {
+        self.input_args
+            .iter()
+            .chain(&self.output_args)
+            .chain(&self.trailing_args)
+    }
+
+    /// How many passes the job is encoded in.
+    pub fn passes(&self) -> u32 {
+        // Hardware encoders do their own lookahead instead.
+        if self.target_size.is_some() && !self.format.is_hardware() {
+            return self.format.passes().max(2);
+        }
+
+        self.format.passes()
+    }
+
+    /// The arguments to ffmpeg for the pass of this job which writes the output, not including the
+    /// program.
+    ///
+    /// These write straight to the output and leave the regular stats of ffmpeg enabled, so that
+    /// they can be run by hand.
+    pub fn argv(&self) -> Vec<OsString> {
+        let log = self.default_passlog();
+        let count = self.passes();
+        self.args(&self.output, false, self.pass(count, &log))
+    }
+
+    /// The arguments to ffmpeg for every pass of this job, in the order they are run.
+    ///
+    /// Like [`TranscodeJob::argv`], these can be run by hand. Passes before the last one only
+    /// analyze the input and write their statistics next to the output.
+    pub fn pass_argv(&self) -> Vec<Vec<OsString>> {
+        let log = self.default_passlog();
+
+        (1..=self.passes())
+            .map(|number| self.args(&self.output, false, self.pass(number, &log)))
+            .collect()
+    }
+
+    /// The expected duration of the output, based on the source and the requested window.
+    pub fn expected_duration(&self) -> Result<Option<f64>, failure::Error> {
+        let start = self.start_seconds()?;
+
+        let end = match self.end.as_ref() {
+            Some(end) => Some(timestamp::parse(end)?),
+            None => None,
+        };
+
+        let duration = match self.duration.as_ref() {
+            Some(duration) => Some(timestamp::parse(duration)?),
+            None => None,
+        };
+
+        let remaining = self.source_duration.map(|total| (total - start).max(0.0));
+
+        // -t takes priority over -to.
+        let window = match (duration, end) {
+            (Some(duration), _) => Some(duration),
+            (None, Some(end)) => Some((end - start).max(0.0)),
+            (None, None) => None,
+        };
+
+        Ok(match (window, remaining) {
+            (Some(window), Some(remaining)) => Some(window.min(remaining)),
+            (window, remaining) => window.or(remaining),
+        })
+    }
+
+    /// Where in the input the transcode starts, in seconds.
+    fn start_seconds(&self) -> Result<f64, failure::Error> {
+        match self.start.as_ref() {
+            Some(start) => timestamp::parse(start),
+            None => Ok(0.0),
+        }
+    }
+
+    /// Build the arguments encoding a sample of the video with the given CRF.
+    ///
+    /// The sample starts `offset` seconds into the output and is written as Matroska, without
+    /// audio.
+    pub(crate) fn sample_args(
+        &self,
+        offset: f64,
+        length: f64,
+        crf: u32,
+        output: &Path,
+    ) -> Result<Vec<OsString>, failure::Error> {
+        let format = self.tuned_format(Some(crf));
+        let start = self.start_seconds()? + offset;
+
+        let mut cmd = process::Command::new("");
+        cmd.args(["-hide_banner", "-loglevel", "warning", "-y"]);
+        cmd.args([
+            "-ss",
+            &format!("{:.3}", start),
+            "-t",
+            &format!("{:.3}", length),
+        ]);
+        format.input_args(&mut cmd);
+        cmd.args(&self.input_args);
+        cmd.arg("-i");
+        cmd.arg(&self.input);
+        cmd.args(["-map", "0:v:0"]);
+        format.output_args(self.target.as_ref(), &mut cmd);
+        cmd.args(["-an", "-f", "matroska"]);
+        cmd.arg(output);
+
+        Ok(cmd.get_args().map(|a| a.to_owned()).collect())
+    }
+
+    /// Build the arguments scoring an encoded sample against the same part of the input.
+    ///
+    /// The score is printed to stderr, which is why the log level is left at its default.
+    pub(crate) fn score_args(
+        &self,
+        sample: &Path,
+        offset: f64,
+        length: f64,
+        metric: Metric,
+    ) -> Result<Vec<OsString>, failure::Error> {
+        let start = self.start_seconds()? + offset;
+        let pix_fmt = non_empty(&self.format.settings.video.pix_fmt).unwrap_or("yuv420p");
+
+        // The sample is compared at the resolution and frame rate of the source, with both sides
+        // starting at the same timestamp.
+        let mut distorted = Vec::new();
+        let mut reference = Vec::new();
+
+        if let Some(target) = self.target.as_ref() {
+            if let (Some((width, height)), Some(_)) = (target.source_size, target.size) {
+                distorted.push(format!("scale={}:{}", width, height));
+            }
+
+            reference.extend(target.fps_filter());
+        }
+
+        for chain in [&mut distorted, &mut reference] {
+            chain.push(format!("format={}", pix_fmt));
+            chain.push(String::from("setpts=PTS-STARTPTS"));
+        }
+
+        let graph = format!(
+            "[0:v]{}[distorted];[1:v]{}[reference];[distorted][reference]{}",
+            distorted.join(","),
+            reference.join(","),
+            metric.filter()
+        );
+
+        let mut cmd = process::Command::new("");
+        cmd.args(["-hide_banner", "-nostats"]);
+        cmd.arg("-i");
+        cmd.arg(sample);
+        cmd.args([
+            "-ss",
+            &format!("{:.3}", start),
+            "-t",
+            &format!("{:.3}", length),
+        ]);
+        cmd.arg("-i");
+        cmd.arg(&self.input);
+        cmd.args(["-lavfi", &graph, "-f", "null", "-"]);
+
+        Ok(cmd.get_args().map(|a| a.to_owned()).collect())
+    }
+
+    /// The prefix of the statistics written by a multi-pass encode that is run by hand.
+    fn default_passlog(&self) -> PathBuf {
+        let mut log = self.output.clone().into_os_string();
+        log.push("-passlog");
+        PathBuf::from(log)
+    }
+
+    /// Describe the given pass of this job, or `None` if it is encoded in a single pass.
+    pub(crate) fn pass<'a>(&self, number: u32, log: &'a Path) -> Option<Pass<'a>> {
+        let count = self.passes();
+
+        if count < 2 {
+            return None;
+        }
+
+        Some(Pass { number, count, log })
+    }
+
+    /// Build the arguments writing to the given output path.
+    ///
+    /// If `progress` is set, ffmpeg reports its progress on stdout instead of printing stats.
+    /// Passes before the last one discard their output.
+    pub(crate) fn args(
+        &self,
+        output: &Path,
+        progress: bool,
+        pass: Option<Pass<'_>>,
+    ) -> Vec<OsString> {
+        let format = self.tuned_format(self.crf);
+
+        // Only used to collect arguments, the program is never run.
+        let mut cmd = process::Command::new("");
+        cmd.args(["-hide_banner", "-loglevel", "warning"]);
+
+        if progress {
+            cmd.args(["-nostats", "-progress", "pipe:1"]);
+        }
+
+        // Existing outputs are dealt with before a job is constructed.
+        cmd.arg("-y");
+
+        if let Some(start) = self.start.as_ref() {
+            cmd.args(["-ss", start.as_str()]);
+        }
+
+        if let Some(end) = self.end.as_ref() {
+            cmd.args(["-to", end.as_str()]);
+        }
+
+        if let Some(duration) = self.duration.as_ref() {
+            cmd.args(["-t", duration.as_str()]);
+        }
+
+        format.input_args(&mut cmd);
+        cmd.args(&self.input_args);
+        cmd.arg("-i");
+        cmd.arg(&self.input);
+
+        // Streams mapped by the format are replaced by those mapped explicitly.
+        let streams = self.streams();
+
+        let map = match (streams, format.settings.map.as_deref()) {
+            (Some(streams), _) => &streams.map[..],
+            (None, Some(map)) if self.map.is_empty() => map,
+            _ => &self.map[..],
+        };
+
+        for m in map {
+            cmd.arg("-map");
+            cmd.arg(m);
+        }
+
+        format.output_args(self.target.as_ref(), &mut cmd);
+
+        if let Some(streams) = streams {
+            cmd.args(&streams.args);
+        }
+
+        if let Some(pass) = pass {
+            cmd.arg("-pass");
+            cmd.arg(pass.number.to_string());
+            cmd.arg("-passlogfile");
+            cmd.arg(pass.log);
+
+            // Only the video is analyzed, and the muxer set by the format is overridden.
+            if pass.number < pass.count {
+                cmd.args(["-an", "-f", "null"]);
+            }
+        }
+
+        cmd.args(&self.output_args);
+        cmd.args(&self.trailing_args);
+
+        match pass {
+            Some(pass) if pass.number < pass.count => cmd.arg("-"),
+            _ => cmd.arg(output),
+        };
+
+        cmd.get_args().map(|a| a.to_owned()).collect()
+    }
+}
+
+/// A single pass of a multi-pass encode.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Pass<'a> {
+    /// The number of the pass, starting at 1.
+    pub number: u32,
+    /// The total number of passes.
+    pub count: u32,
+    /// The prefix of the statistics shared between passes.
+    pub log: &'a Path,
+}
@@ -0,0 +1,48 @@
+//! A small library to simplify fast transcoding through ffmpeg.
+//!
+//! Formats are resolved into a [`Format`], which is combined with an input and an output into a
+//! [`TranscodeJob`]. A job can be inspected through the arguments it passes to ffmpeg, or run
+//! through [`Ffmpeg`], which reports what is going on to an implementation of [`Events`].
+
+mod backend;
+mod cancel;
+mod capabilities;
+mod child;
+mod config;
+mod diagnostics;
+mod events;
+mod ffmpeg;
+mod ffprobe;
+mod format;
+mod job;
+mod passthrough;
+mod programs;
+mod progress;
+mod quality;
+mod runner;
+mod settings;
+pub mod size;
+mod target;
+mod temp;
+pub mod timestamp;
+mod version;
+
+pub use self::{
+    backend::Backend,
+    cancel::Cancel,
+    capabilities::{Capabilities, Missing, Requirements},
+    config::Config,
+    diagnostics::{Class, TranscodeError},
+    events::{Events, Outcome, Plan},
+    ffmpeg::Ffmpeg,
+    ffprobe::{Chapter, Container, Ffprobe, MediaInfo, Rational, Stream, StreamKind},
+    format::{Builtin, Format},
+    job::TranscodeJob,
+    programs::Programs,
+    progress::Progress,
+    quality::Metric,
+    runner::{Process, Runner, Spawned, System},
+    settings::{Audio, Settings, Subtitle, Video},
+    target::{Bounds, Limits, VideoTarget},
+    version::Version,
+};
@@ -1,196 +1,165 @@
+mod inputs;
+mod naming;
+mod report;
+mod scheduler;
+mod shell;
+mod tracker;
+
+use self::{
+    inputs::Inputs,
+    naming::{Conflict, Naming, Resolved, Vars},
+    report::{BatchEntry, Job, OutputFormat, Reporter, Status},
+    scheduler::Scheduler,
+};
 use failure::{bail, format_err};
 use std::{
+    collections::HashMap,
+    fs,
     path::{Path, PathBuf},
     process,
+    sync::Mutex,
+};
+use tessie::{
+    size, timestamp, Backend, Builtin, Config, Ffmpeg, Ffprobe, Format, MediaInfo, Outcome,
+    Programs, StreamKind, TranscodeJob,
 };
 
-const VERSION: &'static str = env!("CARGO_PKG_VERSION");
-
-/// The format to transcode to.
-pub enum Format {
-    /// YouTube-optimized format (1080p @ 60fps)
-    YouTube,
-    /// High-quality GIF.
-    Gif,
-    /// Copy input parameters.
-    Copy,
-}
-
-impl Format {
-    pub fn input_args(&self, cmd: &mut process::Command) {
-        use self::Format::*;
-
-        match *self {
-            YouTube => {
-                cmd.args(&["-y", "-hwaccel", "cuvid", "-c:v", "h264_cuvid"]);
-            }
-            _ => {}
-        }
-    }
-
-    /// Construct an output file based on the input.
-    pub fn output_file(&self, input: &Path) -> Result<PathBuf, failure::Error> {
-        use self::Format::*;
-
-        let mut output = input.to_owned();
-
-        match *self {
-            YouTube => {
-                output.set_extension("mp4");
-            }
-            Gif => {
-                output.set_extension("gif");
-            }
-            Copy => {
-                let e = match output.extension().and_then(|s| s.to_str()) {
-                    Some(ext) => ext.to_string(),
-                    None => failure::bail!("expected extension: {}", output.display()),
-                };
-
-                output.set_extension(format!("copy.{}", e));
-            }
-        }
-
-        Ok(output)
-    }
-
-    pub fn output_args(&self, cmd: &mut process::Command) {
-        use self::Format::*;
-
-        match *self {
-            YouTube => {
-                cmd.args(&[
-                    "-c:v",
-                    "h264_nvenc",
-                    "-coder",
-                    "1",
-                    "-preset",
-                    "llhq",
-                    "-rc:v",
-                    "vbr_minqp",
-                    "-qmin:v",
-                    "21",
-                    "-qmax:v",
-                    "23",
-                    "-b:v",
-                    "5000k",
-                    "-maxrate:v",
-                    "8000k",
-                    "-profile:v",
-                    "high",
-                    "-bf",
-                    "2",
-                    "-c:a",
-                    "aac",
-                    "-profile:a",
-                    "aac_low",
-                    "-b:a",
-                    "384k",
-                    "-f",
-                    "mp4",
-                ]);
-            }
-            Gif => {
-                cmd.args(&[
-                    "-filter_complex",
-                    "[0:v] fps=12,scale=280:-1,split [a][b];[a] palettegen [p];[b][p] paletteuse",
-                    "-f",
-                    "gif",
-                ]);
-            }
-            Copy => {
-                cmd.args(&["-c:v", "copy", "-c:a", "copy"]);
-            }
-        }
-    }
-}
-
-/// ffmpeg abstraction.
-#[derive(Default)]
-struct Ffmpeg {
-    map: Vec<String>,
-    start: Option<String>,
-    end: Option<String>,
-    duration: Option<String>,
-}
-
-impl Ffmpeg {
-    const COMMAND: &'static str = "ffmpeg";
-
-    /// Create a new ffmpeg abstraction testing that we have a workable command in the process.
-    pub fn new() -> Result<Ffmpeg, failure::Error> {
-        let o = process::Command::new(Self::COMMAND)
-            .arg("-version")
-            .output()?;
-
-        if !o.status.success() {
-            bail!("could not run: ffmpeg --version`: {:?}", o);
-        }
-
-        Ok(Ffmpeg::default())
-    }
-
-    /// Transcode a single file from input to output.
-    pub fn transcode(
-        &self,
-        format: Format,
-        input: impl AsRef<Path>,
-        output: impl AsRef<Path>,
-    ) -> Result<(), failure::Error> {
-        let mut cmd = process::Command::new(Self::COMMAND);
-
-        if let Some(start) = self.start.as_ref() {
-            cmd.args(&["-ss", start.as_str()]);
-        }
-
-        if let Some(end) = self.end.as_ref() {
-            cmd.args(&["-to", end.as_str()]);
-        }
-
-        if let Some(duration) = self.duration.as_ref() {
-            cmd.args(&["-t", duration.as_str()]);
-        }
-
-        format.input_args(&mut cmd);
-        cmd.arg("-i");
-        cmd.arg(input.as_ref());
-
-        for m in &self.map {
-            cmd.arg("-map");
-            cmd.arg(m);
-        }
-
-        format.output_args(&mut cmd);
-        cmd.arg(output.as_ref());
-
-        println!("{:?}", cmd);
-
-        if !cmd.status()?.success() {
-            bail!("failed to run command");
-        }
-
-        Ok(())
-    }
-}
+const VERSION: &str = env!("CARGO_PKG_VERSION");
 
 fn opts() -> clap::App<'static, 'static> {
     clap::App::new("tessie")
         .version(VERSION)
         .author("John-John Tedro <udoprog@tedro.se>")
         .about("Transcodes videos using ffmpeg into different formats.")
+        .setting(clap::AppSettings::SubcommandsNegateReqs)
+        .arg(
+            clap::Arg::with_name("ffmpeg")
+                .help("Path to the ffmpeg program (default: ffmpeg from PATH).")
+                .long("ffmpeg")
+                .value_name("path")
+                .takes_value(true)
+                .global(true),
+        )
+        .arg(
+            clap::Arg::with_name("ffprobe")
+                .help("Path to the ffprobe program (default: next to --ffmpeg, or from PATH).")
+                .long("ffprobe")
+                .value_name("path")
+                .takes_value(true)
+                .global(true),
+        )
+        .subcommand(
+            clap::SubCommand::with_name("probe")
+                .about("Print information about a media file.")
+                .arg(
+                    clap::Arg::with_name("input")
+                        .help("Input file to probe.")
+                        .required(true),
+                ),
+        )
         .arg(
             clap::Arg::with_name("input")
-                .help("Input file to transcode.")
+                .help("Input files, directories or glob patterns to transcode.")
+                .multiple(true)
                 .required(true),
         )
+        .arg(
+            clap::Arg::with_name("ffmpeg-args")
+                .help("Arguments passed to ffmpeg right before the output path.")
+                .multiple(true)
+                .last(true),
+        )
+        .arg(
+            clap::Arg::with_name("input-arg")
+                .help("Pass an argument to ffmpeg before the input, like `--input-arg=-re`.")
+                .long("input-arg")
+                .value_name("arg")
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-arg")
+                .help("Pass an argument to ffmpeg after the output options of the format.")
+                .long("output-arg")
+                .value_name("arg")
+                .multiple(true)
+                .number_of_values(1)
+                .allow_hyphen_values(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output")
+                .help("Path to write the output to. Only valid for a single input.")
+                .short("o")
+                .takes_value(true)
+                .conflicts_with_all(&["output-dir", "output-template"]),
+        )
+        .arg(
+            clap::Arg::with_name("output-dir")
+                .help("Directory to write outputs to (default: next to each input).")
+                .long("output-dir")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("output-template")
+                .help("Template for output filenames, like `{stem}-{format}-{start}.{ext}`. Available variables: stem, name, ext, format, start, end, duration.")
+                .long("output-template")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("overwrite")
+                .help("Overwrite outputs which already exist.")
+                .long("overwrite")
+                .conflicts_with_all(&["skip-existing", "suffix-on-conflict"]),
+        )
+        .arg(
+            clap::Arg::with_name("skip-existing")
+                .help("Skip inputs whose output already exists.")
+                .long("skip-existing")
+                .conflicts_with("suffix-on-conflict"),
+        )
+        .arg(
+            clap::Arg::with_name("suffix-on-conflict")
+                .help("Add a numbered suffix to outputs which already exist, like `clip-1.mp4`.")
+                .long("suffix-on-conflict"),
+        )
+        .arg(
+            clap::Arg::with_name("recursive")
+                .help("Look for inputs in subdirectories of input directories.")
+                .short("r")
+                .long("recursive"),
+        )
+        .arg(
+            clap::Arg::with_name("ext")
+                .help("Only transcode files with these extensions from directories and globs (e.g. mp4,mkv).")
+                .long("ext")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1),
+        )
         .arg(
             clap::Arg::with_name("format")
                 .help(
-                    "The format of the transcode (default: YouTube). Available formats: YouTube, Gif.",
+                    "The format of the transcode (default: YouTube). Available formats: YouTube, Gif, \
+                     Copy, WebM, Av1, Archive, or the name of a preset from the configuration.",
                 )
                 .short("f")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("set")
+                .help(
+                    "Override a setting of the format, like `video.bitrate=8000k`. An empty value \
+                     unsets the setting.",
+                )
+                .long("set")
+                .value_name("key=value")
+                .multiple(true)
+                .number_of_values(1)
+                .takes_value(true),
+        )
         .arg(
             clap::Arg::with_name("start")
                 .help("At which timestamp we should transcode from.")
@@ -206,7 +175,10 @@ fn opts() -> clap::App<'static, 'static> {
         .arg(
             clap::Arg::with_name("map")
                 .short("m")
-                .help("Map tracks (0:0 is usually video, 0:1=first audio).")
+                .help(
+                    "Map tracks (0:0 is usually video, 0:1=first audio), replacing the tracks \
+                     mapped by the format.",
+                )
                 .multiple(true)
                 .takes_value(true),
         )
@@ -216,38 +188,509 @@ fn opts() -> clap::App<'static, 'static> {
                 .short("d")
                 .takes_value(true),
         )
+        .arg(
+            clap::Arg::with_name("target-size")
+                .help(
+                    "Fit the output within the given size, like 25M or 8MiB, by encoding the video at \
+                     the bitrate which fills it.",
+                )
+                .long("target-size")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("target-vmaf")
+                .help(
+                    "Encode the video at the constant quality which reaches the given VMAF score, \
+                     like 95, found by scoring short samples of the input.",
+                )
+                .long("target-vmaf")
+                .takes_value(true)
+                .conflicts_with("target-size"),
+        )
+        .arg(
+            clap::Arg::with_name("hwaccel")
+                .help(
+                    "Hardware acceleration to use (default: auto). Available: auto, nvidia, vaapi, qsv, software.",
+                )
+                .long("hwaccel")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("jobs")
+                .help("How many inputs to transcode at the same time (default: 1).")
+                .short("j")
+                .long("jobs")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("hw-jobs")
+                .help("How many of the jobs may use a hardware encoder at the same time (default: 2).")
+                .long("hw-jobs")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("fail-fast")
+                .help("Stop all jobs as soon as one of them fails.")
+                .long("fail-fast"),
+        )
+        .arg(
+            clap::Arg::with_name("dry-run")
+                .help("Print what would be transcoded and the ffmpeg command for it, without running anything.")
+                .long("dry-run"),
+        )
+        .arg(
+            clap::Arg::with_name("print-command")
+                .help("Print the ffmpeg command of each transcode.")
+                .long("print-command"),
+        )
+        .arg(
+            clap::Arg::with_name("output-format")
+                .help("How to report progress (default: human). Available: human, json.")
+                .long("output-format")
+                .takes_value(true),
+        )
 }
 
-fn main() -> Result<(), failure::Error> {
-    let m = opts().get_matches();
+/// Print media information in a human-readable form.
+fn print_media_info(input: &Path, info: &MediaInfo) {
+    let mut header = vec![info
+        .container
+        .format
+        .clone()
+        .unwrap_or_else(|| String::from("unknown"))];
 
-    let mut ffmpeg = Ffmpeg::new()?;
+    if let Some(duration) = info.duration() {
+        header.push(format!("duration {}", timestamp::format(duration)));
+    }
 
-    let format = match m.value_of("format") {
-        None | Some("youtube") | Some("YouTube") => Format::YouTube,
-        Some("gif") | Some("Gif") => Format::Gif,
-        Some("copy") | Some("Copy") => Format::Copy,
-        Some(other) => bail!("illegal --format: {}", other),
-    };
+    if let Some(bit_rate) = info.container.bit_rate {
+        header.push(format!("{} kb/s", bit_rate / 1000));
+    }
+
+    if let Some(size) = info.container.size {
+        header.push(format!("{} bytes", size));
+    }
+
+    println!("{}: {}", input.display(), header.join(", "));
+
+    for s in &info.streams {
+        let mut parts = vec![s.codec.clone().unwrap_or_else(|| String::from("unknown"))];
+
+        if let StreamKind::Video = s.kind {
+            if let (Some(width), Some(height)) = (s.width, s.height) {
+                parts.push(format!("{}x{}", width, height));
+            }
+
+            if let Some(frame_rate) = s.frame_rate {
+                parts.push(format!("{} fps", frame_rate));
+            }
+
+            if let Some(pix_fmt) = s.pix_fmt.as_ref() {
+                parts.push(pix_fmt.clone());
+            }
+
+            if s.attached_pic {
+                parts.push(String::from("attached picture"));
+            }
+        }
+
+        if let Some(channels) = s.channels {
+            parts.push(format!("{} channels", channels));
+        }
+
+        if let Some(sample_rate) = s.sample_rate {
+            parts.push(format!("{} Hz", sample_rate));
+        }
 
-    ffmpeg.map = m
-        .values_of("map")
-        .map(|o| o.map(|s| s.to_string()).collect())
-        .unwrap_or_default();
-    ffmpeg.start = m.value_of("start").map(String::from);
-    ffmpeg.end = m.value_of("end").map(String::from);
-    ffmpeg.duration = m.value_of("duration").map(String::from);
+        if let Some(bit_rate) = s.bit_rate {
+            parts.push(format!("{} kb/s", bit_rate / 1000));
+        }
+
+        if let Some(title) = s.title.as_ref() {
+            parts.push(format!("{:?}", title));
+        }
+
+        match s.language.as_ref() {
+            Some(language) => println!(
+                "  #{} {} ({}): {}",
+                s.index,
+                s.kind,
+                language,
+                parts.join(", ")
+            ),
+            None => println!("  #{} {}: {}", s.index, s.kind, parts.join(", ")),
+        }
+    }
+
+    for (i, c) in info.chapters.iter().enumerate() {
+        println!(
+            "  chapter {}: {} - {}{}",
+            i + 1,
+            timestamp::format(c.start),
+            timestamp::format(c.end),
+            c.title
+                .as_ref()
+                .map(|t| format!(": {}", t))
+                .unwrap_or_default()
+        );
+    }
+}
 
+/// Entrypoint for the probe subcommand.
+fn probe(m: &clap::ArgMatches) -> Result<(), failure::Error> {
     let input = m
         .value_of("input")
         .map(PathBuf::from)
         .ok_or_else(|| format_err!("missing <input> argument"))?;
-    let output = format.output_file(&input)?;
 
-    if output.is_file() {
-        bail!("output already exists: {}", output.display());
+    let config = Config::load(Builtin::NAMES)?;
+    let programs = programs(m, &config);
+
+    let ffprobe = Ffprobe::new(programs.ffprobe)?;
+    let info = ffprobe.probe(&input)?;
+    print_media_info(&input, &info);
+    Ok(())
+}
+
+fn main() -> Result<(), failure::Error> {
+    let m = opts().get_matches();
+
+    if let Some(m) = m.subcommand_matches("probe") {
+        return probe(m);
+    }
+
+    let output_format = match m.value_of("output-format") {
+        None => OutputFormat::Human,
+        Some(other) => other.parse::<OutputFormat>()?,
+    };
+
+    let mut reporter = Reporter::new(output_format);
+    reporter.print_command = m.is_present("print-command");
+    let reporter = Mutex::new(reporter);
+
+    match run(&m, &reporter) {
+        Ok(Exit::Success) => {}
+        Ok(exit) => process::exit(exit.code()),
+        Err(e) => {
+            let mut reporter = reporter.lock().unwrap_or_else(|e| e.into_inner());
+            reporter.error(None, None, &e);
+            process::exit(Exit::Failed.code());
+        }
     }
 
-    ffmpeg.transcode(format, &input, &output)?;
     Ok(())
 }
+
+/// How a run ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Exit {
+    /// All inputs were transcoded or skipped.
+    Success,
+    /// Any of the inputs failed.
+    Failed,
+    /// tessie was interrupted by a signal.
+    Interrupted,
+}
+
+impl Exit {
+    /// The exit code of the process.
+    fn code(self) -> i32 {
+        match self {
+            Exit::Success => 0,
+            Exit::Failed => 1,
+            // Like shells do for processes terminated by SIGINT.
+            Exit::Interrupted => 130,
+        }
+    }
+}
+
+/// Run all transcodes as configured on the command line.
+fn run(m: &clap::ArgMatches, reporter: &Mutex<Reporter>) -> Result<Exit, failure::Error> {
+    let backend = match m.value_of("hwaccel") {
+        None | Some("auto") => None,
+        Some(other) => Some(other.parse::<Backend>()?),
+    };
+
+    let config = Config::load(Builtin::NAMES)?;
+    let programs = programs(m, &config);
+    let ffmpeg = Ffmpeg::new(&programs.ffmpeg, backend)?;
+
+    let format_name = m.value_of("format").unwrap_or("YouTube");
+    let mut format = Format::parse(format_name, &config, ffmpeg.backend())?;
+    format.adapt(ffmpeg.capabilities());
+
+    for assignment in m.values_of("set").into_iter().flatten() {
+        format
+            .settings
+            .set(assignment)
+            .map_err(|e| format_err!("illegal --set: {}", e))?;
+    }
+
+    ffmpeg.check(&format)?;
+
+    let start = m.value_of("start");
+    let end = m.value_of("end");
+    let duration = m.value_of("duration");
+
+    let target_size = match m.value_of("target-size") {
+        Some(target_size) => Some(
+            size::parse(target_size).map_err(|e| format_err!("illegal --target-size: {}", e))?,
+        ),
+        None => None,
+    };
+
+    let target_vmaf = match m.value_of("target-vmaf") {
+        Some(target_vmaf) => match target_vmaf.parse::<f64>() {
+            Ok(score) if (0.0..=100.0).contains(&score) => Some(score),
+            _ => bail!(
+                "illegal --target-vmaf: expected a score from 0 to 100, but got `{}`",
+                target_vmaf
+            ),
+        },
+        None => None,
+    };
+
+    let strings = |name| m.values_of(name).into_iter().flatten();
+
+    // Constructs the job for a single input with the options from the command line.
+    let new_job = |input: &Path, output: &Path| {
+        let mut job = TranscodeJob::new(format.clone(), input, output);
+
+        if let Some(start) = start {
+            job = job.start(start);
+        }
+
+        if let Some(end) = end {
+            job = job.end(end);
+        }
+
+        if let Some(duration) = duration {
+            job = job.duration(duration);
+        }
+
+        if let Some(target_size) = target_size {
+            job = job.target_size(target_size);
+        }
+
+        if let Some(target_vmaf) = target_vmaf {
+            job = job.target_quality(target_vmaf);
+        }
+
+        job = strings("map").fold(job, TranscodeJob::map);
+        job = strings("input-arg").fold(job, TranscodeJob::input_arg);
+        job = strings("output-arg").fold(job, TranscodeJob::output_arg);
+        strings("ffmpeg-args").fold(job, TranscodeJob::trailing_arg)
+    };
+
+    let inputs = Inputs {
+        recursive: m.is_present("recursive"),
+        extensions: m
+            .values_of("ext")
+            .map(|v| v.flat_map(|e| e.split(',')).map(String::from).collect())
+            .unwrap_or_default(),
+    };
+
+    let inputs = inputs.expand(m.values_of("input").into_iter().flatten())?;
+
+    if inputs.is_empty() {
+        bail!("no inputs to transcode");
+    }
+
+    let naming = Naming {
+        output: m.value_of_os("output").map(PathBuf::from),
+        dir: m.value_of_os("output-dir").map(PathBuf::from),
+        template: m.value_of("output-template").map(String::from),
+        conflict: if m.is_present("overwrite") {
+            Conflict::Overwrite
+        } else if m.is_present("skip-existing") {
+            Conflict::Skip
+        } else if m.is_present("suffix-on-conflict") {
+            Conflict::Suffix
+        } else {
+            Conflict::Error
+        },
+    };
+
+    if naming.output.is_some() && inputs.len() > 1 {
+        bail!("-o can only be used with a single input, use --output-dir instead");
+    }
+
+    let dry_run = m.is_present("dry-run");
+
+    // Nothing is written during a dry run.
+    if let Some(dir) = naming.dir.as_ref().filter(|_| !dry_run) {
+        fs::create_dir_all(dir)
+            .map_err(|e| format_err!("failed to create directory: {}: {}", dir.display(), e))?;
+    }
+
+    let format_name = format.to_string();
+    let mut jobs = Vec::new();
+    let mut entries = Vec::new();
+    let mut outputs = HashMap::<PathBuf, PathBuf>::new();
+
+    for input in inputs {
+        let ext = format.extension(&input)?;
+
+        let vars = Vars {
+            format: &format_name,
+            ext: &ext,
+            start,
+            end,
+            duration,
+        };
+
+        let output = naming.output(&input, format.default_template(), &vars)?;
+
+        // Only numbered suffixes can be used to avoid collisions within the batch.
+        if naming.conflict != Conflict::Suffix {
+            if let Some(other) = outputs.get(&output) {
+                bail!(
+                    "{} and {} would both be written to {}",
+                    other.display(),
+                    input.display(),
+                    output.display()
+                );
+            }
+        }
+
+        // An output which is the same file as its input is an error regardless of the policy.
+        let resolved = new_job(&input, &output)
+            .check_output()
+            .and_then(|()| naming.resolve(output, |p| p.exists() || outputs.contains_key(p)));
+
+        let status = match resolved {
+            Ok(Resolved::Write(output)) => {
+                outputs.insert(output.clone(), input.clone());
+                jobs.push((entries.len(), new_job(&input, &output)));
+                Status::Failed(String::from("cancelled"))
+            }
+            Ok(Resolved::Skip(output)) => {
+                let mut reporter = reporter.lock().unwrap_or_else(|e| e.into_inner());
+                reporter.skipped(&input, &output);
+                Status::Skipped(output)
+            }
+            Err(e) => {
+                let mut reporter = reporter.lock().unwrap_or_else(|e| e.into_inner());
+                reporter.error(None, Some(&input), &e);
+                Status::Failed(e.to_string())
+            }
+        };
+
+        entries.push(BatchEntry { input, status });
+    }
+
+    let ffprobe = Ffprobe::new(&programs.ffprobe)?;
+
+    if dry_run {
+        let mut failed = entries
+            .iter()
+            .any(|e| matches!(e.status, Status::Failed(..)));
+
+        for (id, (_, transcode)) in jobs.iter().enumerate() {
+            let job = Job::new(reporter, id);
+
+            let result = ffprobe
+                .probe(transcode.input())
+                .and_then(|info| ffmpeg.dry_run(&transcode.clone().source(&info), &job));
+
+            if let Err(e) = result {
+                job.error(transcode.input(), &e);
+                failed = true;
+            }
+        }
+
+        return Ok(if failed { Exit::Failed } else { Exit::Success });
+    }
+
+    let scheduler = Scheduler {
+        jobs: parse_count(m, "jobs", 1)?,
+        hw_jobs: parse_count(m, "hw-jobs", 2)?,
+    };
+
+    let fail_fast = m.is_present("fail-fast");
+
+    let cancel = ffmpeg.cancel().clone();
+    ctrlc::set_handler(move || cancel.interrupt())?;
+
+    let hw = format.is_hardware();
+
+    let results = scheduler.run(
+        &jobs,
+        ffmpeg.cancel(),
+        |_| hw,
+        |id, (_, transcode)| {
+            let job = Job::new(reporter, id);
+
+            match transcode_one(&ffmpeg, &ffprobe, transcode, &job) {
+                Ok(outcome) => {
+                    job.result(&outcome);
+                    Status::Done(outcome.output)
+                }
+                Err(e) => {
+                    job.error(transcode.input(), &e);
+
+                    if fail_fast {
+                        ffmpeg.cancel().cancel();
+                    }
+
+                    Status::Failed(e.to_string())
+                }
+            }
+        },
+    );
+
+    for ((index, _), status) in jobs.into_iter().zip(results) {
+        if let Some(status) = status {
+            entries[index].status = status;
+        }
+    }
+
+    if entries.len() > 1 {
+        let mut reporter = reporter.lock().unwrap_or_else(|e| e.into_inner());
+        reporter.summary(&entries);
+    }
+
+    if ffmpeg.cancel().is_interrupted() {
+        return Ok(Exit::Interrupted);
+    }
+
+    if entries
+        .iter()
+        .any(|e| matches!(e.status, Status::Failed(..)))
+    {
+        return Ok(Exit::Failed);
+    }
+
+    Ok(Exit::Success)
+}
+
+/// Decide which ffmpeg and ffprobe programs to run.
+fn programs(m: &clap::ArgMatches, config: &Config) -> Programs {
+    Programs::resolve(
+        m.value_of_os("ffmpeg").map(PathBuf::from),
+        m.value_of_os("ffprobe").map(PathBuf::from),
+        config,
+    )
+}
+
+/// Parse an optional positive count argument.
+fn parse_count(m: &clap::ArgMatches, name: &str, default: usize) -> Result<usize, failure::Error> {
+    match m.value_of(name) {
+        Some(value) => match value.parse::<usize>() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => bail!("illegal --{}: {}", name, value),
+        },
+        None => Ok(default),
+    }
+}
+
+/// Transcode a single input.
+fn transcode_one(
+    ffmpeg: &Ffmpeg,
+    ffprobe: &Ffprobe,
+    transcode: &TranscodeJob,
+    job: &Job,
+) -> Result<Outcome, failure::Error> {
+    let info = ffprobe.probe(transcode.input())?;
+    Ok(ffmpeg.transcode(&transcode.clone().source(&info), job)?)
+}
@@ -0,0 +1,251 @@
+//! Deciding where outputs are written.
+
+use failure::{bail, format_err};
+use std::path::{Path, PathBuf};
+
+/// Values available to output filename templates.
+#[derive(Debug, Default)]
+pub struct Vars<'a> {
+    /// Name of the format.
+    pub format: &'a str,
+    /// Extension of the output, without the leading dot.
+    pub ext: &'a str,
+    pub start: Option<&'a str>,
+    pub end: Option<&'a str>,
+    pub duration: Option<&'a str>,
+}
+
+/// What to do when an output already exists.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Conflict {
+    /// Fail the input.
+    #[default]
+    Error,
+    /// Replace the existing output.
+    Overwrite,
+    /// Skip the input.
+    Skip,
+    /// Write to a new path with a numbered suffix, like `clip-1.mp4`.
+    Suffix,
+}
+
+/// How an output was resolved against existing files.
+#[derive(Debug)]
+pub enum Resolved {
+    /// Write to the given path.
+    Write(PathBuf),
+    /// Skip the input since the given output already exists.
+    Skip(PathBuf),
+}
+
+/// Decides the output path of each input.
+#[derive(Debug, Default)]
+pub struct Naming {
+    /// An explicit output path, only valid for a single input.
+    pub output: Option<PathBuf>,
+    /// Directory to write outputs to instead of next to their inputs.
+    pub dir: Option<PathBuf>,
+    /// Template for the output filename.
+    pub template: Option<String>,
+    /// What to do when an output already exists.
+    pub conflict: Conflict,
+}
+
+impl Naming {
+    /// Decide the output path for the given input.
+    ///
+    /// `default_template` is used if no template was configured.
+    pub fn output(
+        &self,
+        input: &Path,
+        default_template: &str,
+        vars: &Vars,
+    ) -> Result<PathBuf, failure::Error> {
+        if let Some(output) = self.output.as_ref() {
+            return Ok(output.clone());
+        }
+
+        let template = self.template.as_deref().unwrap_or(default_template);
+        let name = render(template, input, vars)?;
+
+        if name.is_empty() || name.contains(['/', '\\']) {
+            bail!(
+                "illegal output name from template `{}`: {:?}",
+                template,
+                name
+            );
+        }
+
+        let dir = match self.dir.as_ref() {
+            Some(dir) => dir.as_path(),
+            None => input.parent().unwrap_or_else(|| Path::new("")),
+        };
+
+        Ok(dir.join(name))
+    }
+
+    /// Resolve the output against paths which are already taken according to the conflict
+    /// policy.
+    pub fn resolve(
+        &self,
+        output: PathBuf,
+        taken: impl Fn(&Path) -> bool,
+    ) -> Result<Resolved, failure::Error> {
+        if !taken(&output) {
+            return Ok(Resolved::Write(output));
+        }
+
+        match self.conflict {
+            Conflict::Error => bail!("output already exists: {}", output.display()),
+            Conflict::Overwrite => Ok(Resolved::Write(output)),
+            Conflict::Skip => Ok(Resolved::Skip(output)),
+            Conflict::Suffix => {
+                let stem = output
+                    .file_stem()
+                    .ok_or_else(|| format_err!("output has no file name: {}", output.display()))?
+                    .to_string_lossy()
+                    .into_owned();
+
+                let ext = output
+                    .extension()
+                    .map(|e| format!(".{}", e.to_string_lossy()))
+                    .unwrap_or_default();
+
+                for n in 1.. {
+                    let candidate = output.with_file_name(format!("{}-{}{}", stem, n, ext));
+
+                    if !taken(&candidate) {
+                        return Ok(Resolved::Write(candidate));
+                    }
+                }
+
+                unreachable!()
+            }
+        }
+    }
+}
+
+/// Render a filename template like `{stem}-{format}-{start}.{ext}`.
+///
+/// Available variables are `stem`, `name`, `ext`, `format`, `start`, `end` and `duration`.
+/// Timestamps have their `:` replaced with `-` to be safe in filenames, and render as empty if
+/// they are not set. Literal braces are written as `{{` and `}}`.
+pub fn render(template: &str, input: &Path, vars: &Vars) -> Result<String, failure::Error> {
+    let mut out = String::new();
+    let mut it = template.chars();
+
+    while let Some(c) = it.next() {
+        match c {
+            '{' => {
+                let mut name = String::new();
+
+                loop {
+                    match it.next() {
+                        Some('{') if name.is_empty() => {
+                            out.push('{');
+                            break;
+                        }
+                        Some('}') => {
+                            out.push_str(&lookup(&name, input, vars)?);
+                            break;
+                        }
+                        Some(c) => name.push(c),
+                        None => bail!("unterminated variable in template: {}", template),
+                    }
+                }
+            }
+            '}' => {
+                if it.next() != Some('}') {
+                    bail!("unmatched `}}` in template: {}", template);
+                }
+
+                out.push('}');
+            }
+            c => out.push(c),
+        }
+    }
+
+    Ok(out)
+}
+
+/// Look up a single template variable.
+fn lookup(name: &str, input: &Path, vars: &Vars) -> Result<String, failure::Error> {
+    let timestamp = |value: Option<&str>| value.unwrap_or_default().replace(':', "-");
+
+    Ok(match name.trim() {
+        "stem" => input
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .ok_or_else(|| format_err!("input has no file name: {}", input.display()))?,
+        "name" => input
+            .file_name()
+            .map(|s| s.to_string_lossy().into_owned())
+            .ok_or_else(|| format_err!("input has no file name: {}", input.display()))?,
+        "ext" => vars.ext.to_string(),
+        "format" => vars.format.to_lowercase(),
+        "start" => timestamp(vars.start),
+        "end" => timestamp(vars.end),
+        "duration" => timestamp(vars.duration),
+        other => bail!("unknown template variable: {{{}}}", other),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, Vars};
+    use std::path::Path;
+
+    fn vars() -> Vars<'static> {
+        Vars {
+            format: "YouTube",
+            ext: "mp4",
+            start: Some("00:01:30"),
+            ..Vars::default()
+        }
+    }
+
+    #[test]
+    fn renders_variables() {
+        let input = Path::new("dir/clip.mkv");
+
+        assert_eq!(
+            render("{stem}-{format}.{ext}", input, &vars()).unwrap(),
+            "clip-youtube.mp4"
+        );
+        assert_eq!(
+            render("{name}@{start}{end}", input, &vars()).unwrap(),
+            "clip.mkv@00-01-30"
+        );
+        assert_eq!(
+            render("{ stem }.{ext}", input, &vars()).unwrap(),
+            "clip.mp4"
+        );
+    }
+
+    #[test]
+    fn renders_escaped_braces() {
+        let input = Path::new("clip.mkv");
+
+        assert_eq!(
+            render("{{{stem}}}.{ext}", input, &vars()).unwrap(),
+            "{clip}.mp4"
+        );
+        assert_eq!(render("{{}}", input, &vars()).unwrap(), "{}");
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        let input = Path::new("clip.mkv");
+        let error = |template| render(template, input, &vars()).unwrap_err().to_string();
+
+        assert_eq!(error("{size}.mp4"), "unknown template variable: {size}");
+        assert_eq!(
+            error("{stem.mp4"),
+            "unterminated variable in template: {stem.mp4"
+        );
+        assert_eq!(
+            error("{stem}}.mp4"),
+            "unmatched `}` in template: {stem}}.mp4"
+        );
+    }
+}
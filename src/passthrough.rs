@@ -0,0 +1,73 @@
+//! Raw ffmpeg arguments passed through from the command line.
+
+use std::ffi::OsString;
+
+/// Options which can be given any number of times without overriding each other.
+const REPEATABLE: &[&str] = &["-map", "-metadata", "-disposition", "-attach"];
+
+/// Find options among the passed through arguments which override options already managed by
+/// tessie.
+///
+/// `argv` is the full command, including the passed through arguments.
+pub fn conflicts<'a>(argv: &[OsString], passthrough: &'a [String]) -> Vec<&'a str> {
+    let mut conflicts = Vec::new();
+
+    for arg in passthrough {
+        let option = match normalize(arg) {
+            Some(option) => option,
+            None => continue,
+        };
+
+        let base = option.split(':').next().unwrap_or_default();
+
+        if REPEATABLE.contains(&base) || conflicts.contains(&arg.as_str()) {
+            continue;
+        }
+
+        let total = argv
+            .iter()
+            .filter(|a| a.to_str().and_then(normalize).as_ref() == Some(&option))
+            .count();
+
+        let passed = passthrough
+            .iter()
+            .filter(|a| normalize(a).as_ref() == Some(&option))
+            .count();
+
+        if total > passed {
+            conflicts.push(arg.as_str());
+        }
+    }
+
+    conflicts
+}
+
+/// Normalize an option to a canonical spelling, or return `None` if the argument is a value.
+fn normalize(arg: &str) -> Option<String> {
+    let mut chars = arg.chars();
+
+    // Negative numbers are values.
+    match (chars.next(), chars.next()) {
+        (Some('-'), Some(c)) if c.is_ascii_alphabetic() => {}
+        _ => return None,
+    }
+
+    let option = match arg {
+        "-vcodec" => "-c:v",
+        "-acodec" => "-c:a",
+        "-scodec" => "-c:s",
+        "-vb" => "-b:v",
+        "-ab" => "-b:a",
+        "-filter:v" => "-vf",
+        "-filter:a" => "-af",
+        "-lavfi" => "-filter_complex",
+        _ => match arg.strip_prefix("-codec") {
+            Some(rest) if rest.is_empty() || rest.starts_with(':') => {
+                return Some(format!("-c{}", rest));
+            }
+            _ => arg,
+        },
+    };
+
+    Some(option.to_string())
+}
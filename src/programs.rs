@@ -0,0 +1,76 @@
+//! Locations of the ffmpeg and ffprobe programs.
+
+use crate::config::Config;
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+/// Environment variable overriding the ffmpeg program.
+pub const FFMPEG_ENV: &str = "TESSIE_FFMPEG";
+/// Environment variable overriding the ffprobe program.
+pub const FFPROBE_ENV: &str = "TESSIE_FFPROBE";
+
+/// The ffmpeg and ffprobe programs to run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Programs {
+    /// The ffmpeg program.
+    pub ffmpeg: PathBuf,
+    /// The ffprobe program.
+    pub ffprobe: PathBuf,
+}
+
+impl Default for Programs {
+    /// Look up both programs in `PATH`.
+    fn default() -> Programs {
+        Programs {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            ffprobe: PathBuf::from("ffprobe"),
+        }
+    }
+}
+
+impl Programs {
+    /// Decide which programs to run.
+    ///
+    /// Each program is taken from the first of: the given path, its environment variable, the
+    /// configuration and finally `PATH`. If only ffmpeg is specified, an ffprobe next to it is
+    /// preferred since they usually come from the same build.
+    pub fn resolve(ffmpeg: Option<PathBuf>, ffprobe: Option<PathBuf>, config: &Config) -> Programs {
+        let defaults = Programs::default();
+
+        let ffmpeg = ffmpeg
+            .or_else(|| env_path(FFMPEG_ENV))
+            .or_else(|| config.ffmpeg.clone());
+
+        let ffprobe = ffprobe
+            .or_else(|| env_path(FFPROBE_ENV))
+            .or_else(|| config.ffprobe.clone())
+            .or_else(|| sibling(ffmpeg.as_deref()?, "ffprobe"));
+
+        Programs {
+            ffmpeg: ffmpeg.unwrap_or(defaults.ffmpeg),
+            ffprobe: ffprobe.unwrap_or(defaults.ffprobe),
+        }
+    }
+}
+
+/// A non-empty path from the environment.
+fn env_path(name: &str) -> Option<PathBuf> {
+    env::var_os(name)
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// The program with the given name in the same directory as `program`, if it exists.
+fn sibling(program: &Path, name: &str) -> Option<PathBuf> {
+    let dir = program.parent().filter(|d| !d.as_os_str().is_empty())?;
+    let mut sibling = dir.join(name);
+
+    // Keep extensions like `.exe`.
+    if let Some(ext) = program.extension() {
+        sibling.set_extension(ext);
+    }
+
+    Some(sibling).filter(|s| s.is_file())
+}
@@ -0,0 +1,63 @@
+//! Parsing of the progress reported by `ffmpeg -progress`.
+
+use crate::timestamp;
+
+/// A single progress report from ffmpeg.
+#[derive(Debug, Clone, Default)]
+pub struct Progress {
+    /// How far into the output we are, in seconds.
+    pub out_time: Option<f64>,
+    /// Frames encoded per second.
+    pub fps: Option<f64>,
+    /// Speed relative to realtime.
+    pub speed: Option<f64>,
+    /// Total size of the output so far, in bytes.
+    pub total_size: Option<u64>,
+    /// If this is the final report.
+    pub done: bool,
+    /// The pass of a multi-pass encode this report belongs to, and how many passes there are.
+    pub pass: Option<(u32, u32)>,
+}
+
+/// Parser for the `key=value` stream written by `-progress`.
+///
+/// Each report is a block of keys terminated by `progress=continue` or `progress=end`.
+#[derive(Default)]
+pub(crate) struct Parser {
+    current: Progress,
+}
+
+impl Parser {
+    /// Feed a single line to the parser, returning a report once a block is complete.
+    pub(crate) fn feed(&mut self, line: &str) -> Option<Progress> {
+        let mut it = line.trim().splitn(2, '=');
+
+        let (key, value) = match (it.next(), it.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => return None,
+        };
+
+        match key {
+            "out_time" => {
+                self.current.out_time = timestamp::parse(value).ok().filter(|t| *t >= 0.0);
+            }
+            "fps" => {
+                self.current.fps = value.parse().ok();
+            }
+            "speed" => {
+                self.current.speed = value.trim_end_matches('x').trim().parse().ok();
+            }
+            "total_size" => {
+                self.current.total_size = value.parse().ok();
+            }
+            "progress" => {
+                let mut progress = std::mem::take(&mut self.current);
+                progress.done = value == "end";
+                return Some(progress);
+            }
+            _ => {}
+        }
+
+        None
+    }
+}
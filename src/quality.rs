@@ -0,0 +1,268 @@
+//! Searching for the constant quality which reaches a target score, by encoding and scoring short
+//! samples of the input.
+
+use crate::capabilities::Capabilities;
+use std::fmt;
+
+/// How many samples are taken from the input.
+const SAMPLES: usize = 3;
+
+/// The longest a single sample is, in seconds.
+const SAMPLE_LENGTH: f64 = 4.0;
+
+/// How many CRF values are probed between the bounds of the range, at most.
+const MAX_STEPS: usize = 4;
+
+/// The metric samples are scored with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Metric {
+    /// Video Multi-Method Assessment Fusion, from 0 to 100.
+    Vmaf,
+    /// Structural similarity, scaled from 0 to 100 to be comparable to a VMAF target.
+    Ssim,
+}
+
+impl Metric {
+    /// Pick the best metric that ffmpeg was built with, if it has any.
+    pub fn detect(capabilities: &Capabilities) -> Option<Metric> {
+        if capabilities.has_filter("libvmaf") {
+            Some(Metric::Vmaf)
+        } else if capabilities.has_filter("ssim") {
+            Some(Metric::Ssim)
+        } else {
+            None
+        }
+    }
+
+    /// The filter comparing the distorted video in its first input to the reference in its second.
+    pub fn filter(self) -> &'static str {
+        match self {
+            Metric::Vmaf => "libvmaf",
+            Metric::Ssim => "ssim",
+        }
+    }
+
+    /// Parse the score from what the filter printed to stderr.
+    ///
+    /// libvmaf prints `VMAF score: 95.12`, while ssim prints `SSIM Y:0.99 ... All:0.98 (17.2)`.
+    pub fn parse(self, stderr: &str) -> Option<f64> {
+        let (needle, scale) = match self {
+            Metric::Vmaf => ("VMAF score:", 1.0),
+            Metric::Ssim => ("All:", 100.0),
+        };
+
+        let line = stderr.lines().rev().find(|l| l.contains(needle))?;
+        let value = &line[line.find(needle)? + needle.len()..];
+        let value = value.split_whitespace().next()?;
+        Some(value.parse::<f64>().ok()? * scale)
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Metric::Vmaf => "vmaf".fmt(fmt),
+            Metric::Ssim => "ssim".fmt(fmt),
+        }
+    }
+}
+
+/// The range of CRF values worth searching for the given encoder, from best to worst quality.
+pub fn crf_range(codec: &str) -> Option<(u32, u32)> {
+    match codec {
+        "libx264" | "libx265" => Some((16, 34)),
+        "libvpx-vp9" | "libsvtav1" | "libaom-av1" => Some((20, 50)),
+        _ => None,
+    }
+}
+
+/// Where samples are taken from an output of the given duration, as offsets and lengths in
+/// seconds.
+pub fn samples(duration: f64) -> Vec<(f64, f64)> {
+    let length = SAMPLE_LENGTH.min(duration / SAMPLES as f64);
+    let step = duration / SAMPLES as f64;
+
+    (0..SAMPLES)
+        .map(|i| {
+            let middle = step * (i as f64 + 0.5);
+            ((middle - length / 2.0).max(0.0), length)
+        })
+        .collect()
+}
+
+/// The result of a search.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Found {
+    /// The highest CRF which reached the target, or the lowest one searched if none did.
+    pub crf: u32,
+    /// The score of the samples encoded with the CRF.
+    pub score: f64,
+    /// If the target was reached.
+    pub reached: bool,
+}
+
+/// Search for the highest CRF in `range` which scores at least `target`.
+///
+/// Scores drop as the CRF increases, so the bounds of the range are scored first and the CRF
+/// hitting the target is interpolated between the closest scores on either side of it.
+pub fn search<E>(
+    range: (u32, u32),
+    target: f64,
+    mut score: impl FnMut(u32) -> Result<f64, E>,
+) -> Result<Found, E> {
+    let (mut lo, mut hi) = range;
+
+    let mut lo_score = score(lo)?;
+
+    if lo_score < target {
+        return Ok(Found {
+            crf: lo,
+            score: lo_score,
+            reached: false,
+        });
+    }
+
+    let mut hi_score = score(hi)?;
+
+    if hi_score >= target {
+        return Ok(Found {
+            crf: hi,
+            score: hi_score,
+            reached: true,
+        });
+    }
+
+    for _ in 0..MAX_STEPS {
+        if hi - lo <= 1 {
+            break;
+        }
+
+        let crf = interpolate((lo, lo_score), (hi, hi_score), target).clamp(lo + 1, hi - 1);
+        let s = score(crf)?;
+
+        if s >= target {
+            lo = crf;
+            lo_score = s;
+        } else {
+            hi = crf;
+            hi_score = s;
+        }
+    }
+
+    Ok(Found {
+        crf: lo,
+        score: lo_score,
+        reached: true,
+    })
+}
+
+/// Interpolate the CRF scoring `target` between two scored CRF values, rounded down to stay on
+/// the side of quality.
+fn interpolate(a: (u32, f64), b: (u32, f64), target: f64) -> u32 {
+    let (a_crf, a_score) = (f64::from(a.0), a.1);
+    let (b_crf, b_score) = (f64::from(b.0), b.1);
+
+    if (a_score - b_score).abs() < f64::EPSILON {
+        return a.0;
+    }
+
+    let crf = a_crf + (target - a_score) * (b_crf - a_crf) / (b_score - a_score);
+    crf.floor().max(0.0) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{interpolate, search, Found, Metric};
+
+    /// Search with scores falling linearly from `at_zero` by one per CRF, recording the scored
+    /// CRF values.
+    fn linear(range: (u32, u32), target: f64, at_zero: f64) -> (Found, Vec<u32>) {
+        let mut scored = Vec::new();
+
+        let found = search(range, target, |crf| {
+            scored.push(crf);
+            Ok::<_, ()>(at_zero - f64::from(crf))
+        })
+        .unwrap();
+
+        (found, scored)
+    }
+
+    #[test]
+    fn interpolates_between_scores() {
+        assert_eq!(interpolate((16, 104.0), (34, 86.0), 95.0), 25);
+        // Rounded down to stay on the side of quality.
+        assert_eq!(interpolate((16, 100.0), (34, 90.0), 95.5), 24);
+        assert_eq!(interpolate((20, 90.0), (30, 90.0), 95.0), 20);
+    }
+
+    #[test]
+    fn searches_between_bounds() {
+        let (found, scored) = linear((16, 34), 95.0, 120.0);
+
+        assert_eq!(
+            found,
+            Found {
+                crf: 25,
+                score: 95.0,
+                reached: true
+            }
+        );
+        assert_eq!(scored, [16, 34, 25, 26]);
+    }
+
+    #[test]
+    fn interpolated_crf_stays_within_bounds() {
+        let mut scored = Vec::new();
+
+        // Scores which drop off a cliff make the interpolation land on the bounds.
+        let found = search((16, 34), 95.0, |crf| {
+            scored.push(crf);
+            Ok::<_, ()>(if crf <= 17 { 99.0 } else { 10.0 })
+        })
+        .unwrap();
+
+        assert_eq!(found.crf, 17);
+        assert!(found.reached);
+        assert!(scored[2..].iter().all(|crf| (17..=33).contains(crf)));
+    }
+
+    #[test]
+    fn upper_bound_reaches_target() {
+        let (found, scored) = linear((16, 34), 80.0, 120.0);
+
+        assert_eq!(found.crf, 34);
+        assert!(found.reached);
+        assert_eq!(scored, [16, 34]);
+    }
+
+    #[test]
+    fn unreachable_target() {
+        let (found, scored) = linear((16, 34), 99.0, 110.0);
+
+        assert_eq!(
+            found,
+            Found {
+                crf: 16,
+                score: 94.0,
+                reached: false
+            }
+        );
+        assert_eq!(scored, [16]);
+    }
+
+    #[test]
+    fn parses_vmaf_score() {
+        let stderr = "frame=  100\n[Parsed_libvmaf_4 @ 0x1] VMAF score: 95.123456\n";
+        assert_eq!(Metric::Vmaf.parse(stderr), Some(95.123456));
+        assert_eq!(Metric::Vmaf.parse("Conversion failed!"), None);
+    }
+
+    #[test]
+    fn parses_ssim_score() {
+        let stderr = "[Parsed_ssim_4 @ 0x1] SSIM Y:0.990000 (20.0) U:0.980000 (17.0) \
+                      V:0.970000 (15.2) All:0.985000 (18.2)\n";
+        assert_eq!(Metric::Ssim.parse(stderr), Some(98.5));
+        assert_eq!(Metric::Ssim.parse("VMAF score: 95.0"), None);
+    }
+}
@@ -0,0 +1,605 @@
+//! Reporting of what tessie is doing, either for humans or as newline-delimited JSON events.
+
+use crate::{
+    shell,
+    tracker::{self, Tracker},
+};
+use failure::bail;
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    io::{self, IsTerminal as _, Write},
+    path::{Path, PathBuf},
+    str,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+use tessie::{timestamp, Class, Events, Metric, Outcome, Plan, Progress, TranscodeError};
+
+/// How often progress is printed as plain lines when stderr is not a terminal.
+const PLAIN_INTERVAL: Duration = Duration::from_secs(10);
+
+/// How to report what is going on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Human-readable output with a progress bar.
+    Human,
+    /// Newline-delimited JSON events on stdout.
+    Json,
+}
+
+impl str::FromStr for OutputFormat {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "human" => OutputFormat::Human,
+            "json" => OutputFormat::Json,
+            other => bail!("illegal --output-format: {}", other),
+        })
+    }
+}
+
+/// The status of a single input in a batch.
+pub enum Status {
+    /// Transcoded into the given output.
+    Done(PathBuf),
+    /// Skipped since the given output already exists.
+    Skipped(PathBuf),
+    /// Failed with the given error.
+    Failed(String),
+}
+
+/// The result of a single input in a batch.
+pub struct BatchEntry {
+    pub input: PathBuf,
+    pub status: Status,
+}
+
+/// The result of a single input in the summary event.
+#[derive(Serialize)]
+struct SummaryEntry {
+    input: String,
+    status: &'static str,
+    output: Option<String>,
+    error: Option<String>,
+}
+
+/// A single JSON event.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+enum Event<'a> {
+    Plan {
+        input: String,
+        output: String,
+        format: &'a str,
+        backend: &'a str,
+        video: Option<String>,
+        duration: Option<f64>,
+        /// The commands of the passes which run before `command`.
+        first_passes: Vec<String>,
+        argv: Vec<String>,
+        /// The argv rendered as a shell command line.
+        command: String,
+        dry_run: bool,
+    },
+    Progress {
+        input: String,
+        out_time: Option<f64>,
+        fps: Option<f64>,
+        speed: Option<f64>,
+        total_size: Option<u64>,
+        percent: Option<f64>,
+        eta: Option<f64>,
+        /// The pass of a multi-pass encode, counting from 1.
+        pass: Option<u32>,
+        passes: Option<u32>,
+    },
+    Warning {
+        input: Option<String>,
+        message: &'a str,
+    },
+    Quality {
+        input: String,
+        crf: u32,
+        score: f64,
+        metric: String,
+    },
+    Result {
+        input: String,
+        output: String,
+        size: u64,
+        duration: Option<f64>,
+        elapsed: f64,
+    },
+    Error {
+        input: Option<String>,
+        message: String,
+        causes: Vec<String>,
+        /// The class of a failed ffmpeg invocation.
+        class: Option<&'static str>,
+        /// The last relevant lines printed by ffmpeg.
+        stderr: Vec<String>,
+        hint: Option<&'static str>,
+    },
+    Skipped {
+        input: String,
+        output: String,
+    },
+    Summary {
+        succeeded: usize,
+        skipped: usize,
+        failed: usize,
+        results: Vec<SummaryEntry>,
+    },
+}
+
+/// A transcode which is currently running.
+struct Active {
+    input: PathBuf,
+    tracker: Tracker,
+    /// The last rendered progress line.
+    line: Option<String>,
+    /// When progress was last printed as a plain line.
+    printed: Option<Instant>,
+}
+
+/// Reports what is going on in the configured output format.
+///
+/// Several jobs can be active at the same time, each identified by a number. In human mode each
+/// active job gets its own progress line at the bottom of the terminal.
+pub struct Reporter {
+    format: OutputFormat,
+    /// Print the command of each transcode in human mode.
+    pub print_command: bool,
+    active: BTreeMap<usize, Active>,
+    /// Number of progress lines currently drawn on stderr.
+    drawn: usize,
+    /// Whether stderr is a terminal which progress lines can be redrawn on.
+    tty: bool,
+}
+
+impl Reporter {
+    /// Construct a new reporter for the given output format.
+    pub fn new(format: OutputFormat) -> Reporter {
+        Reporter {
+            format,
+            print_command: false,
+            active: BTreeMap::new(),
+            drawn: 0,
+            tty: io::stderr().is_terminal(),
+        }
+    }
+
+    /// Report the plan for a transcode that is about to start.
+    pub fn plan(&mut self, job: usize, plan: &Plan) {
+        match self.format {
+            OutputFormat::Human => {
+                self.clear();
+
+                let action = if plan.dry_run {
+                    "would transcode"
+                } else {
+                    "transcoding"
+                };
+
+                println!(
+                    "{}: {} -> {}",
+                    action,
+                    plan.input.display(),
+                    plan.output.display()
+                );
+                println!("backend: {}", plan.backend);
+
+                if let Some(target) = plan.target {
+                    println!("video: {}", target);
+                }
+
+                if self.print_command || plan.dry_run {
+                    for argv in &plan.first_passes {
+                        println!("{}", shell::join(argv));
+                    }
+
+                    println!("{}", shell::join(&plan.argv));
+                }
+            }
+            OutputFormat::Json => {
+                self.emit(&Event::Plan {
+                    input: plan.input.to_string_lossy().into_owned(),
+                    output: plan.output.to_string_lossy().into_owned(),
+                    format: &plan.format,
+                    backend: &plan.backend,
+                    video: plan.target.map(|t| t.to_string()),
+                    duration: plan.duration,
+                    first_passes: plan.first_passes.iter().map(shell::join).collect(),
+                    argv: plan
+                        .argv
+                        .iter()
+                        .map(|a| a.to_string_lossy().into_owned())
+                        .collect(),
+                    command: shell::join(&plan.argv),
+                    dry_run: plan.dry_run,
+                });
+            }
+        }
+
+        // Nothing is tracked for transcodes which won't run.
+        if plan.dry_run {
+            return;
+        }
+
+        self.active.insert(
+            job,
+            Active {
+                input: plan.input.to_owned(),
+                tracker: Tracker::new(plan.duration),
+                line: None,
+                printed: None,
+            },
+        );
+
+        if plan.duration.is_none() {
+            self.warning(
+                Some(plan.input),
+                "could not determine the duration of the output, progress is unavailable",
+            );
+        }
+
+        self.draw();
+    }
+
+    /// Report progress of a running transcode.
+    pub fn progress(&mut self, job: usize, progress: &Progress) {
+        let active = match self.active.get_mut(&job) {
+            Some(active) => active,
+            None => return,
+        };
+
+        match self.format {
+            OutputFormat::Human if self.tty => {
+                active.line = Some(tracker::render(&active.tracker, progress));
+                self.clear();
+                self.draw();
+            }
+            OutputFormat::Human => {
+                let due = active
+                    .printed
+                    .is_none_or(|printed| printed.elapsed() >= PLAIN_INTERVAL);
+
+                if due || progress.done {
+                    active.printed = Some(Instant::now());
+                    let line = tracker::render(&active.tracker, progress);
+                    eprintln!("{}: {}", active.input.display(), line);
+                }
+            }
+            OutputFormat::Json => {
+                let estimate = active.tracker.estimate(progress);
+
+                let event = Event::Progress {
+                    input: active.input.to_string_lossy().into_owned(),
+                    out_time: progress.out_time,
+                    fps: progress.fps,
+                    speed: progress.speed,
+                    total_size: progress.total_size,
+                    percent: estimate.map(|e| e.ratio * 100.0),
+                    eta: estimate.and_then(|e| e.eta),
+                    pass: progress.pass.map(|(pass, _)| pass),
+                    passes: progress.pass.map(|(_, passes)| passes),
+                };
+
+                self.emit(&event);
+            }
+        }
+    }
+
+    /// Report a warning, optionally associated with an input.
+    pub fn warning(&mut self, input: Option<&Path>, message: &str) {
+        match self.format {
+            OutputFormat::Human => {
+                self.clear();
+
+                match input {
+                    Some(input) => eprintln!("warning: {}: {}", input.display(), message),
+                    None => eprintln!("warning: {}", message),
+                }
+
+                self.draw();
+            }
+            OutputFormat::Json => {
+                self.emit(&Event::Warning {
+                    input: input.map(|i| i.to_string_lossy().into_owned()),
+                    message,
+                });
+            }
+        }
+    }
+
+    /// Report the score of samples encoded with a CRF, while searching for a target quality.
+    pub fn quality(&mut self, input: &Path, crf: u32, score: f64, metric: Metric) {
+        match self.format {
+            OutputFormat::Human => {
+                self.clear();
+                println!(
+                    "quality: {}: crf {} scores {:.2} ({})",
+                    input.display(),
+                    crf,
+                    score,
+                    metric
+                );
+                self.draw();
+            }
+            OutputFormat::Json => {
+                self.emit(&Event::Quality {
+                    input: input.to_string_lossy().into_owned(),
+                    crf,
+                    score,
+                    metric: metric.to_string(),
+                });
+            }
+        }
+    }
+
+    /// Report that a transcode finished successfully.
+    pub fn result(&mut self, job: usize, outcome: &Outcome) {
+        self.clear();
+        self.active.remove(&job);
+
+        match self.format {
+            OutputFormat::Human => {
+                println!(
+                    "done: {} ({:.1} MiB in {})",
+                    outcome.output.display(),
+                    outcome.size as f64 / (1024.0 * 1024.0),
+                    timestamp::format(outcome.elapsed)
+                );
+            }
+            OutputFormat::Json => {
+                self.emit(&Event::Result {
+                    input: outcome.input.to_string_lossy().into_owned(),
+                    output: outcome.output.to_string_lossy().into_owned(),
+                    size: outcome.size,
+                    duration: outcome.duration,
+                    elapsed: outcome.elapsed,
+                });
+            }
+        }
+
+        self.draw();
+    }
+
+    /// Report an error, optionally associated with a job and its input.
+    pub fn error(&mut self, job: Option<usize>, input: Option<&Path>, error: &failure::Error) {
+        self.clear();
+
+        if let Some(job) = job {
+            self.active.remove(&job);
+        }
+
+        let (class, lines) = match error.downcast_ref::<TranscodeError>() {
+            Some(TranscodeError::Failed { class, lines, .. }) => (Some(class), &lines[..]),
+            _ => (None, &[][..]),
+        };
+
+        let hint = class.and_then(Class::hint);
+
+        match self.format {
+            OutputFormat::Human => {
+                match input {
+                    Some(input) => eprintln!("error: {}: {}", input.display(), error),
+                    None => eprintln!("error: {}", error),
+                }
+
+                for cause in error.iter_causes() {
+                    eprintln!("  caused by: {}", cause);
+                }
+
+                for line in lines {
+                    eprintln!("  | {}", line);
+                }
+
+                if let Some(hint) = hint {
+                    eprintln!("  hint: {}", hint);
+                }
+            }
+            OutputFormat::Json => {
+                self.emit(&Event::Error {
+                    input: input.map(|i| i.to_string_lossy().into_owned()),
+                    message: error.to_string(),
+                    causes: error.iter_causes().map(|c| c.to_string()).collect(),
+                    class: class.map(Class::id),
+                    stderr: lines.to_vec(),
+                    hint,
+                });
+            }
+        }
+
+        self.draw();
+    }
+
+    /// Report that an input was skipped since its output already exists.
+    pub fn skipped(&mut self, input: &Path, output: &Path) {
+        self.clear();
+
+        match self.format {
+            OutputFormat::Human => {
+                println!(
+                    "skipped: {} (output exists: {})",
+                    input.display(),
+                    output.display()
+                );
+            }
+            OutputFormat::Json => {
+                self.emit(&Event::Skipped {
+                    input: input.to_string_lossy().into_owned(),
+                    output: output.to_string_lossy().into_owned(),
+                });
+            }
+        }
+
+        self.draw();
+    }
+
+    /// Report a summary of all inputs in a batch.
+    pub fn summary(&mut self, entries: &[BatchEntry]) {
+        let count = |f: fn(&Status) -> bool| entries.iter().filter(|e| f(&e.status)).count();
+
+        let succeeded = count(|s| matches!(s, Status::Done(..)));
+        let skipped = count(|s| matches!(s, Status::Skipped(..)));
+        let failed = count(|s| matches!(s, Status::Failed(..)));
+
+        self.clear();
+
+        match self.format {
+            OutputFormat::Human => {
+                println!(
+                    "summary: {} succeeded, {} skipped, {} failed",
+                    succeeded, skipped, failed
+                );
+
+                for e in entries {
+                    match &e.status {
+                        Status::Done(output) => {
+                            println!("  ok      {} -> {}", e.input.display(), output.display());
+                        }
+                        Status::Skipped(output) => {
+                            println!(
+                                "  skipped {} ({} exists)",
+                                e.input.display(),
+                                output.display()
+                            );
+                        }
+                        Status::Failed(error) => {
+                            println!("  FAILED  {}: {}", e.input.display(), error);
+                        }
+                    }
+                }
+            }
+            OutputFormat::Json => {
+                self.emit(&Event::Summary {
+                    succeeded,
+                    skipped,
+                    failed,
+                    results: entries
+                        .iter()
+                        .map(|e| {
+                            let (status, output, error) = match &e.status {
+                                Status::Done(output) => ("ok", Some(output), None),
+                                Status::Skipped(output) => ("skipped", Some(output), None),
+                                Status::Failed(error) => ("failed", None, Some(error.clone())),
+                            };
+
+                            SummaryEntry {
+                                input: e.input.to_string_lossy().into_owned(),
+                                status,
+                                output: output.map(|o| o.to_string_lossy().into_owned()),
+                                error,
+                            }
+                        })
+                        .collect(),
+                });
+            }
+        }
+    }
+
+    /// Draw the progress lines of all active jobs on stderr.
+    fn draw(&mut self) {
+        if self.format != OutputFormat::Human || !self.tty || self.drawn > 0 {
+            return;
+        }
+
+        let lines = self
+            .active
+            .values()
+            .filter_map(|a| {
+                let line = a.line.as_ref()?;
+
+                // Only label the lines when several jobs are running.
+                Some(if self.active.len() > 1 {
+                    let name = a.input.file_name().unwrap_or(a.input.as_os_str());
+                    format!("{}: {}", name.to_string_lossy(), line)
+                } else {
+                    line.clone()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+
+        for line in &lines {
+            let _ = writeln!(stderr, "{}\x1b[K", line);
+        }
+
+        let _ = stderr.flush();
+        self.drawn = lines.len();
+    }
+
+    /// Clear the progress lines so that other output can be printed above them.
+    fn clear(&mut self) {
+        if self.drawn == 0 {
+            return;
+        }
+
+        let _ = io::stdout().flush();
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        let _ = write!(stderr, "\x1b[{}A\r\x1b[J", self.drawn);
+        let _ = stderr.flush();
+        self.drawn = 0;
+    }
+
+    /// Emit a single JSON event on stdout.
+    fn emit(&self, event: &Event) {
+        if let Ok(line) = serde_json::to_string(event) {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            let _ = writeln!(stdout, "{}", line);
+            let _ = stdout.flush();
+        }
+    }
+}
+
+/// A handle used by a single job to report through a shared reporter.
+pub struct Job<'a> {
+    reporter: &'a Mutex<Reporter>,
+    id: usize,
+}
+
+impl<'a> Job<'a> {
+    /// Construct a handle for the job with the given number.
+    pub fn new(reporter: &'a Mutex<Reporter>, id: usize) -> Job<'a> {
+        Job { reporter, id }
+    }
+
+    /// Report that this job finished successfully.
+    pub fn result(&self, outcome: &Outcome) {
+        self.lock().result(self.id, outcome);
+    }
+
+    /// Report that this job failed.
+    pub fn error(&self, input: &Path, error: &failure::Error) {
+        self.lock().error(Some(self.id), Some(input), error);
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Reporter> {
+        self.reporter.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Events for Job<'_> {
+    fn plan(&self, plan: &Plan<'_>) {
+        self.lock().plan(self.id, plan);
+    }
+
+    fn progress(&self, progress: &Progress) {
+        self.lock().progress(self.id, progress);
+    }
+
+    fn warning(&self, input: &Path, message: &str) {
+        self.lock().warning(Some(input), message);
+    }
+
+    fn quality(&self, input: &Path, crf: u32, score: f64, metric: Metric) {
+        self.lock().quality(input, crf, score, metric);
+    }
+}
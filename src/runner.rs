@@ -0,0 +1,98 @@
+//! Running the processes tessie depends on, which can be replaced to test without ffmpeg.
+
+use crate::child;
+use std::{
+    ffi::OsString,
+    io::{self, Read},
+    process,
+};
+
+/// Runs the ffmpeg and ffprobe processes tessie depends on.
+///
+/// The first element of `argv` is the program to run.
+pub trait Runner: Send + Sync {
+    /// Run a command to completion, capturing its stdout and stderr.
+    fn output(&self, argv: &[OsString]) -> io::Result<process::Output>;
+
+    /// Spawn a long-running command, with its stdout and stderr piped.
+    fn spawn(&self, argv: &[OsString]) -> io::Result<Spawned>;
+}
+
+/// A process which has been spawned by a [`Runner`].
+pub struct Spawned {
+    /// What the process writes to stdout.
+    pub stdout: Box<dyn Read + Send>,
+    /// What the process writes to stderr.
+    pub stderr: Box<dyn Read + Send>,
+    /// Control over the process itself.
+    pub process: Box<dyn Process>,
+}
+
+/// Control over a spawned process.
+pub trait Process: Send {
+    /// Stop the process gracefully, killing it if it doesn't exit in time.
+    fn stop(&mut self) -> io::Result<process::ExitStatus>;
+
+    /// Wait for the process to exit.
+    fn wait(&mut self) -> io::Result<process::ExitStatus>;
+}
+
+/// Runs real processes.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct System;
+
+impl System {
+    /// Construct a command from an argument vector.
+    fn command(argv: &[OsString]) -> io::Result<process::Command> {
+        let (program, args) = argv
+            .split_first()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "empty command"))?;
+
+        let mut cmd = process::Command::new(program);
+        cmd.args(args);
+        Ok(cmd)
+    }
+}
+
+impl Runner for System {
+    fn output(&self, argv: &[OsString]) -> io::Result<process::Output> {
+        Self::command(argv)?.stdin(process::Stdio::null()).output()
+    }
+
+    fn spawn(&self, argv: &[OsString]) -> io::Result<Spawned> {
+        let mut cmd = Self::command(argv)?;
+        // ffmpeg is stopped through stdin when we are interrupted.
+        cmd.stdin(process::Stdio::piped());
+        cmd.stdout(process::Stdio::piped());
+        cmd.stderr(process::Stdio::piped());
+        child::isolate(&mut cmd);
+
+        let mut child = cmd.spawn()?;
+        let stdin = child.stdin.take();
+        let missing = |name| io::Error::other(format!("missing {}", name));
+        let stdout = child.stdout.take().ok_or_else(|| missing("stdout"))?;
+        let stderr = child.stderr.take().ok_or_else(|| missing("stderr"))?;
+
+        Ok(Spawned {
+            stdout: Box::new(stdout),
+            stderr: Box::new(stderr),
+            process: Box::new(SystemProcess { child, stdin }),
+        })
+    }
+}
+
+/// A child process spawned by [`System`].
+struct SystemProcess {
+    child: process::Child,
+    stdin: Option<process::ChildStdin>,
+}
+
+impl Process for SystemProcess {
+    fn stop(&mut self) -> io::Result<process::ExitStatus> {
+        child::stop(&mut self.child, self.stdin.take())
+    }
+
+    fn wait(&mut self) -> io::Result<process::ExitStatus> {
+        self.child.wait()
+    }
+}
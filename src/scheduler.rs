@@ -0,0 +1,164 @@
+//! Runs several jobs concurrently, with a separate limit on jobs using hardware encoders.
+
+use std::{
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+use tessie::Cancel;
+
+/// A scheduler for running jobs concurrently.
+#[derive(Debug, Clone, Copy)]
+pub struct Scheduler {
+    /// The maximum number of jobs to run at the same time.
+    pub jobs: usize,
+    /// The maximum number of jobs using hardware encoders to run at the same time.
+    ///
+    /// Hardware encoders commonly limit the number of concurrent sessions, like NVENC on consumer
+    /// cards.
+    pub hw_jobs: usize,
+}
+
+struct State {
+    started: Vec<bool>,
+    hw_running: usize,
+}
+
+/// A slot taken by a running hardware job, which is released when dropped, even if the job
+/// panicked.
+struct HwSlot<'a> {
+    state: &'a Mutex<State>,
+    cond: &'a Condvar,
+}
+
+impl Drop for HwSlot<'_> {
+    fn drop(&mut self) {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .hw_running -= 1;
+        self.cond.notify_all();
+    }
+}
+
+impl Scheduler {
+    /// Run the given tasks, returning their results in order.
+    ///
+    /// Tasks for which `is_hw` is true count towards the hardware limit. Once cancelled, no more
+    /// tasks are started and their results are `None`.
+    pub fn run<T, R>(
+        &self,
+        tasks: &[T],
+        cancel: &Cancel,
+        is_hw: impl Fn(&T) -> bool + Sync,
+        task: impl Fn(usize, &T) -> R + Sync,
+    ) -> Vec<Option<R>>
+    where
+        T: Sync,
+        R: Send,
+    {
+        let state = Mutex::new(State {
+            started: vec![false; tasks.len()],
+            hw_running: 0,
+        });
+
+        let cond = Condvar::new();
+        let results = Mutex::new((0..tasks.len()).map(|_| None).collect::<Vec<_>>());
+
+        let workers = self.jobs.max(1).min(tasks.len());
+        let hw_jobs = self.hw_jobs.max(1);
+
+        thread::scope(|s| {
+            for _ in 0..workers {
+                s.spawn(|| loop {
+                    let mut guard = state.lock().unwrap_or_else(|e| e.into_inner());
+
+                    // Pick the first task that hasn't been started and which there is room for.
+                    let (index, hw) = loop {
+                        if cancel.is_cancelled() || guard.started.iter().all(|s| *s) {
+                            return;
+                        }
+
+                        let hw_full = guard.hw_running >= hw_jobs;
+
+                        let next = guard
+                            .started
+                            .iter()
+                            .enumerate()
+                            .filter(|(_, started)| !**started)
+                            .map(|(i, _)| (i, is_hw(&tasks[i])))
+                            .find(|(_, hw)| !(*hw && hw_full));
+
+                        match next {
+                            Some(next) => break next,
+                            None => {
+                                guard = cond
+                                    .wait_timeout(guard, Duration::from_millis(100))
+                                    .unwrap_or_else(|e| e.into_inner())
+                                    .0;
+                            }
+                        }
+                    };
+
+                    guard.started[index] = true;
+
+                    let slot = hw.then(|| {
+                        guard.hw_running += 1;
+
+                        HwSlot {
+                            state: &state,
+                            cond: &cond,
+                        }
+                    });
+
+                    drop(guard);
+
+                    let result = task(index, &tasks[index]);
+                    results.lock().unwrap_or_else(|e| e.into_inner())[index] = Some(result);
+                    drop(slot);
+                });
+            }
+        });
+
+        results.into_inner().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Scheduler;
+    use std::{
+        panic,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+    use tessie::Cancel;
+
+    #[test]
+    fn panicking_hw_job_releases_its_slot() {
+        let scheduler = Scheduler {
+            jobs: 2,
+            hw_jobs: 1,
+        };
+
+        let ran = AtomicBool::new(false);
+
+        let result = panic::catch_unwind(panic::AssertUnwindSafe(|| {
+            scheduler.run(
+                &[0, 1],
+                &Cancel::default(),
+                |_| true,
+                |index, _| {
+                    if index == 0 {
+                        panic!("job failed");
+                    }
+
+                    ran.store(true, Ordering::SeqCst);
+                },
+            )
+        }));
+
+        // The panic is propagated once every other job is done.
+        assert!(result.is_err());
+        assert!(ran.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,412 @@
+//! The structured settings of a format, which are turned into ffmpeg arguments.
+//!
+//! Settings are layered: a preset is merged on top of the format it extends, and overrides from
+//! the command line are applied last.
+
+use crate::version::Version;
+use failure::{bail, format_err};
+use serde::{de, Deserialize, Deserializer};
+use std::{fmt, process};
+
+/// Suffixes of encoders which run on hardware.
+const HARDWARE_ENCODERS: &[&str] = &["_nvenc", "_vaapi", "_qsv", "_amf", "_videotoolbox"];
+
+/// Define a group of options which each map to a single ffmpeg flag.
+///
+/// Options are written in the order they are defined. An option set to an empty string is unset,
+/// which allows a preset or override to remove an option of the format it extends.
+macro_rules! options {
+    (
+        $(#[$meta:meta])*
+        pub struct $name:ident {
+            $($(#[$field_meta:meta])* $field:ident => $flag:literal,)*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Default, Deserialize)]
+        #[serde(deny_unknown_fields)]
+        pub struct $name {
+            $(
+                $(#[$field_meta])*
+                #[serde(default, deserialize_with = "scalar")]
+                pub $field: Option<String>,
+            )*
+        }
+
+        impl $name {
+            /// Names of all options.
+            const KEYS: &'static [&'static str] = &[$(stringify!($field),)*];
+
+            /// Override options with those which are set in `other`.
+            fn merge(&mut self, other: &$name) {
+                $(
+                    if other.$field.is_some() {
+                        self.$field = other.$field.clone();
+                    }
+                )*
+            }
+
+            /// Set the option with the given name, returning `false` if there is no such option.
+            fn set(&mut self, key: &str, value: &str) -> bool {
+                match key {
+                    $(stringify!($field) => self.$field = Some(value.to_string()),)*
+                    _ => return false,
+                }
+
+                true
+            }
+
+            /// Add arguments for all options which are set.
+            fn args(&self, cmd: &mut process::Command) {
+                $(
+                    if let Some(value) = non_empty(&self.$field) {
+                        cmd.args([$flag, value]);
+                    }
+                )*
+            }
+        }
+    };
+}
+
+options! {
+    /// Settings of the video encoder.
+    pub struct Video {
+        /// The encoder, or `copy`.
+        codec => "-c:v",
+        coder => "-coder",
+        preset => "-preset",
+        /// Quality/speed trade-off of libvpx, like `good`.
+        deadline => "-deadline",
+        /// Speed of libvpx and libaom, higher is faster.
+        cpu_used => "-cpu-used",
+        /// Row based multithreading of libvpx and libaom.
+        row_mt => "-row-mt",
+        /// Rate control mode of hardware encoders.
+        rc => "-rc:v",
+        crf => "-crf",
+        /// Constant quality of hardware encoders.
+        cq => "-cq:v",
+        qmin => "-qmin:v",
+        qmax => "-qmax:v",
+        bitrate => "-b:v",
+        maxrate => "-maxrate:v",
+        bufsize => "-bufsize:v",
+        profile => "-profile:v",
+        pix_fmt => "-pix_fmt",
+        /// Maximum number of B-frames.
+        bframes => "-bf",
+        /// Codec tag written to the container.
+        tag => "-tag:v",
+    }
+}
+
+impl Video {
+    /// Encode at the given average bitrate in bits per second instead of a constant quality.
+    pub fn average_bitrate(&mut self, bitrate: u64) {
+        let kbps = bitrate / 1000;
+
+        self.crf = None;
+        self.cq = None;
+        self.qmin = None;
+        self.qmax = None;
+        self.bitrate = Some(format!("{}k", kbps));
+        // Peaks are allowed as long as they even out within a couple of seconds.
+        self.maxrate = Some(format!("{}k", kbps * 3 / 2));
+        self.bufsize = Some(format!("{}k", kbps * 2));
+
+        // Rate control modes of hardware encoders might be tied to the quantizers.
+        if non_empty(&self.rc).is_some() {
+            self.rc = Some(String::from("vbr"));
+        }
+    }
+}
+
+options! {
+    /// Settings of the audio encoder.
+    pub struct Audio {
+        /// The encoder, or `copy`.
+        codec => "-c:a",
+        profile => "-profile:a",
+        bitrate => "-b:a",
+        channels => "-ac",
+        sample_rate => "-ar",
+    }
+}
+
+options! {
+    /// Settings of the subtitle encoder.
+    pub struct Subtitle {
+        /// The encoder, or `copy`.
+        codec => "-c:s",
+    }
+}
+
+/// Settings of a format.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Settings {
+    /// Name of the format these settings extend, only used by presets.
+    pub extends: Option<String>,
+    /// Muxer to write the output with, passed to ffmpeg as `-f`.
+    #[serde(default, deserialize_with = "scalar")]
+    pub container: Option<String>,
+    /// Extension of the output, defaults to the container.
+    #[serde(default, deserialize_with = "scalar")]
+    pub extension: Option<String>,
+    /// Settings of the video encoder.
+    #[serde(default)]
+    pub video: Video,
+    /// Settings of the audio encoder.
+    #[serde(default)]
+    pub audio: Audio,
+    /// Settings of the subtitle encoder.
+    #[serde(default)]
+    pub subtitle: Subtitle,
+    /// Streams mapped into the output, unless streams are mapped explicitly.
+    pub map: Option<Vec<String>>,
+    /// Arguments added before the input.
+    pub input_args: Option<Vec<String>>,
+    /// Arguments added after the encoder settings.
+    pub output_args: Option<Vec<String>>,
+    /// Filters added to the end of the video filter chain, passed as `-vf`.
+    pub video_filters: Option<Vec<String>>,
+    /// Filters joined into a chain passed as `-af`.
+    pub audio_filters: Option<Vec<String>>,
+    /// A complete filter graph passed as `-filter_complex`, replacing all other filters.
+    pub filter_complex: Option<String>,
+    /// How many passes the video is encoded in, either 1 or 2.
+    #[serde(default, deserialize_with = "scalar")]
+    pub passes: Option<String>,
+    /// The oldest version of ffmpeg which supports these settings, like `6.0`.
+    #[serde(default, deserialize_with = "scalar")]
+    pub min_ffmpeg_version: Option<String>,
+}
+
+impl Settings {
+    /// Override settings with those which are set in `other`.
+    pub fn merge(&mut self, other: &Settings) {
+        fn merge<T: Clone>(to: &mut Option<T>, from: &Option<T>) {
+            if from.is_some() {
+                *to = from.clone();
+            }
+        }
+
+        merge(&mut self.container, &other.container);
+        merge(&mut self.extension, &other.extension);
+        self.video.merge(&other.video);
+        self.audio.merge(&other.audio);
+        self.subtitle.merge(&other.subtitle);
+        merge(&mut self.map, &other.map);
+        merge(&mut self.input_args, &other.input_args);
+        merge(&mut self.output_args, &other.output_args);
+        merge(&mut self.video_filters, &other.video_filters);
+        merge(&mut self.audio_filters, &other.audio_filters);
+        merge(&mut self.filter_complex, &other.filter_complex);
+        merge(&mut self.passes, &other.passes);
+        merge(&mut self.min_ffmpeg_version, &other.min_ffmpeg_version);
+    }
+
+    /// Apply an override like `video.bitrate=8000k`.
+    pub fn set(&mut self, assignment: &str) -> Result<(), failure::Error> {
+        let mut it = assignment.splitn(2, '=');
+
+        let (key, value) = match (it.next(), it.next()) {
+            (Some(key), Some(value)) => (key.trim(), value.trim()),
+            _ => bail!("expected `<key>=<value>`, but got `{}`", assignment),
+        };
+
+        let found = match key.split_once('.') {
+            Some(("video", option)) => self.video.set(option, value),
+            Some(("audio", option)) => self.audio.set(option, value),
+            Some(("subtitle", option)) => self.subtitle.set(option, value),
+            Some(_) => false,
+            None => match key {
+                "container" => {
+                    self.container = Some(value.to_string());
+                    true
+                }
+                "extension" => {
+                    self.extension = Some(value.to_string());
+                    true
+                }
+                "filter_complex" => {
+                    self.filter_complex = Some(value.to_string());
+                    true
+                }
+                "passes" => {
+                    self.passes = Some(value.to_string());
+                    self.passes().map_err(|e| format_err!("passes: {}", e))?;
+                    true
+                }
+                _ => false,
+            },
+        };
+
+        if !found {
+            bail!("unknown setting `{}`, expected one of: {}", key, Keys);
+        }
+
+        Ok(())
+    }
+
+    /// Validate the settings, with errors prefixed by the offending key.
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        if let Some(container) = self.container.as_deref() {
+            if container.trim().is_empty() {
+                bail!("container: must not be empty");
+            }
+        }
+
+        if let Some(extension) = self.extension.as_deref() {
+            if extension.is_empty() || extension.contains(['.', '/', '\\']) {
+                bail!(
+                    "extension: expected a plain extension like `mp4`, but got {:?}",
+                    extension
+                );
+            }
+        }
+
+        let input_args = self.input_args.as_deref().unwrap_or_default();
+
+        if let Some(index) = input_args.iter().position(|a| a == "-i") {
+            bail!(
+                "input_args[{}]: the input is added by tessie, remove `-i`",
+                index
+            );
+        }
+
+        if self.filter_complex.is_some()
+            && (self.video_filters.is_some() || self.audio_filters.is_some())
+        {
+            bail!("filter_complex: can't be combined with video_filters or audio_filters");
+        }
+
+        self.passes().map_err(|e| format_err!("passes: {}", e))?;
+
+        self.min_ffmpeg_version()
+            .map_err(|e| format_err!("min_ffmpeg_version: {}", e))?;
+
+        Ok(())
+    }
+
+    /// How many passes the video is encoded in.
+    pub fn passes(&self) -> Result<u32, failure::Error> {
+        match non_empty(&self.passes) {
+            Some("1") | None => Ok(1),
+            Some("2") => Ok(2),
+            Some(other) => bail!("expected 1 or 2, but got `{}`", other),
+        }
+    }
+
+    /// The oldest version of ffmpeg which supports these settings, if any.
+    pub fn min_ffmpeg_version(&self) -> Result<Option<Version>, failure::Error> {
+        match non_empty(&self.min_ffmpeg_version) {
+            Some(version) => Ok(Some(version.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Add the arguments of the video encoder.
+    pub fn video_args(&self, cmd: &mut process::Command) {
+        self.video.args(cmd);
+    }
+
+    /// Add the arguments of the audio encoder.
+    pub fn audio_args(&self, cmd: &mut process::Command) {
+        self.audio.args(cmd);
+    }
+
+    /// Add the arguments of the subtitle encoder.
+    pub fn subtitle_args(&self, cmd: &mut process::Command) {
+        self.subtitle.args(cmd);
+    }
+
+    /// The encoders used by these settings, including those selected in the output arguments.
+    pub fn encoders(&self) -> impl Iterator<Item = &str> {
+        let output_args = self.output_args.as_deref().unwrap_or_default();
+
+        let from_args = output_args
+            .iter()
+            .zip(output_args.iter().skip(1))
+            .filter(|(flag, _)| {
+                let flag = flag.split(':').next().unwrap_or_default();
+                matches!(flag, "-c" | "-codec" | "-vcodec" | "-acodec" | "-scodec")
+            })
+            .map(|(_, value)| value.as_str());
+
+        non_empty(&self.video.codec)
+            .into_iter()
+            .chain(non_empty(&self.audio.codec))
+            .chain(non_empty(&self.subtitle.codec))
+            .chain(from_args)
+            .filter(|encoder| *encoder != "copy")
+    }
+
+    /// Test if the settings use a hardware encoder.
+    pub fn is_hardware(&self) -> bool {
+        self.encoders()
+            .any(|e| HARDWARE_ENCODERS.iter().any(|suffix| e.ends_with(suffix)))
+    }
+}
+
+/// Lists all keys which can be set with [`Settings::set`].
+struct Keys;
+
+impl fmt::Display for Keys {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "container, extension, filter_complex, passes")?;
+
+        for key in Video::KEYS {
+            write!(fmt, ", video.{}", key)?;
+        }
+
+        for key in Audio::KEYS {
+            write!(fmt, ", audio.{}", key)?;
+        }
+
+        for key in Subtitle::KEYS {
+            write!(fmt, ", subtitle.{}", key)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The value of an option, unless it is unset.
+pub fn non_empty(value: &Option<String>) -> Option<&str> {
+    value.as_deref().filter(|v| !v.is_empty())
+}
+
+/// Deserialize an option which can be written as a string or a number, like `crf = 22`.
+fn scalar<'de, D>(deserializer: D) -> Result<Option<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Visitor;
+
+    impl de::Visitor<'_> for Visitor {
+        type Value = Option<String>;
+
+        fn expecting(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+            fmt.write_str("a string or a number")
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<Self::Value, E> {
+            Ok(Some(value.to_string()))
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+            Ok(Some(value.to_string()))
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+            Ok(Some(value.to_string()))
+        }
+
+        fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+            Ok(Some(value.to_string()))
+        }
+    }
+
+    deserializer.deserialize_any(Visitor)
+}
@@ -0,0 +1,90 @@
+//! Rendering of commands so that they can be pasted into a POSIX shell.
+
+use std::{borrow::Cow, ffi::OsStr};
+
+/// Quote a single argument for a POSIX shell.
+///
+/// Arguments which only consist of characters that are never special to the shell are left as
+/// they are, everything else is wrapped in single quotes. A leading `=` is quoted too, since zsh
+/// expands it to the path of a command. Arguments which aren't valid UTF-8 are written with
+/// `$'...'`, escaping every byte which isn't printable ASCII, as supported by bash and zsh.
+pub fn quote(arg: &OsStr) -> Cow<'_, str> {
+    let arg = match arg.to_str() {
+        Some(arg) => arg,
+        None => return Cow::Owned(escape(arg.as_encoded_bytes())),
+    };
+
+    let plain = !arg.is_empty()
+        && !arg.starts_with('=')
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=,+@".contains(c));
+
+    if plain {
+        return Cow::Borrowed(arg);
+    }
+
+    // A single quote can't be escaped within single quotes, so it is closed, escaped and reopened.
+    Cow::Owned(format!("'{}'", arg.replace('\'', r"'\''")))
+}
+
+/// Escape arbitrary bytes into a `$'...'` string.
+fn escape(bytes: &[u8]) -> String {
+    let mut out = String::from("$'");
+
+    for &b in bytes {
+        match b {
+            b'\'' | b'\\' => {
+                out.push('\\');
+                out.push(char::from(b));
+            }
+            b' '..=b'~' => out.push(char::from(b)),
+            _ => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+
+    out.push('\'');
+    out
+}
+
+/// Join the given arguments into a single command line.
+pub fn join<I>(argv: I) -> String
+where
+    I: IntoIterator,
+    I::Item: AsRef<OsStr>,
+{
+    argv.into_iter()
+        .map(|a| quote(a.as_ref()).into_owned())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quote;
+    use std::ffi::OsStr;
+
+    #[test]
+    fn quotes_special_characters() {
+        let q = |arg: &str| quote(OsStr::new(arg)).into_owned();
+
+        assert_eq!(q("-c:v"), "-c:v");
+        assert_eq!(q("scale=1280:-2"), "scale=1280:-2");
+        assert_eq!(q(""), "''");
+        assert_eq!(q("my clip.mkv"), "'my clip.mkv'");
+        assert_eq!(q("it's"), r"'it'\''s'");
+        // Special in zsh.
+        assert_eq!(q("50%"), "'50%'");
+        assert_eq!(q("a^b"), "'a^b'");
+        assert_eq!(q("=ffmpeg"), "'=ffmpeg'");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn escapes_invalid_utf8() {
+        use std::os::unix::ffi::OsStrExt as _;
+
+        let arg = OsStr::from_bytes(b"clip\xff it's.mkv");
+        assert_eq!(quote(arg), r"$'clip\xff it\'s.mkv'");
+    }
+}
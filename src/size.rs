@@ -0,0 +1,101 @@
+//! Parsing of file sizes and bitrates, and the bitrate budget of a target file size.
+
+use failure::{bail, format_err};
+
+/// The share of a target size which is spent on the streams, leaving the rest for the overhead of
+/// the container.
+const PAYLOAD: f64 = 0.97;
+
+/// The lowest video bitrate in bits per second which is worth encoding at.
+const MIN_VIDEO_BITRATE: u64 = 50_000;
+
+/// The audio bitrate in bits per second assumed for encoders which don't have one set.
+pub const DEFAULT_AUDIO_BITRATE: u64 = 128_000;
+
+/// Parse a file size into bytes, like `25M`, `25MB`, `500KiB` or `1.5G`.
+///
+/// `K`, `M` and `G` are powers of 1000, while `KiB`, `MiB` and `GiB` are powers of 1024.
+pub fn parse(s: &str) -> Result<u64, failure::Error> {
+    let err = || format_err!("illegal size: {}", s);
+
+    let trimmed = s.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(trimmed.len());
+
+    let (value, unit) = trimmed.split_at(split);
+    let value = value.parse::<f64>().map_err(|_| err())?;
+
+    let scale = match unit.trim() {
+        "" | "B" => 1.0,
+        "K" | "KB" | "k" | "kB" => 1e3,
+        "M" | "MB" => 1e6,
+        "G" | "GB" => 1e9,
+        "KiB" => 1024.0,
+        "MiB" => 1024.0 * 1024.0,
+        "GiB" => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(err()),
+    };
+
+    let bytes = (value * scale).floor() as u64;
+
+    if bytes == 0 {
+        bail!("illegal size: {}: must be larger than zero", s);
+    }
+
+    Ok(bytes)
+}
+
+/// Parse a bitrate as passed to ffmpeg into bits per second, like `384k` or `8M`.
+pub fn parse_bitrate(s: &str) -> Option<u64> {
+    let s = s.trim();
+
+    let (value, scale) = match s.char_indices().last()? {
+        (i, 'k') | (i, 'K') => (&s[..i], 1e3),
+        (i, 'M') => (&s[..i], 1e6),
+        (i, 'G') => (&s[..i], 1e9),
+        _ => (s, 1.0),
+    };
+
+    Some((value.parse::<f64>().ok()? * scale) as u64)
+}
+
+/// Format a size in bytes, like `25.0 MB`.
+pub fn format(bytes: u64) -> String {
+    format!("{:.1} MB", bytes as f64 / 1e6)
+}
+
+/// The video bitrate in bits per second which fits `duration` seconds into `size` bytes, after
+/// `audio` bits per second have been spent on audio.
+pub fn video_bitrate(size: u64, duration: f64, audio: u64) -> Result<u64, failure::Error> {
+    if duration <= 0.0 {
+        bail!("can't fit an empty output into a target size");
+    }
+
+    let total = size as f64 * 8.0 * PAYLOAD / duration;
+    let video = total - audio as f64;
+
+    if video < MIN_VIDEO_BITRATE as f64 {
+        bail!(
+            "target size {} is too small for {:.1}s of video with {}k of audio",
+            format(size),
+            duration,
+            audio / 1000
+        );
+    }
+
+    Ok(video as u64)
+}
+
+/// Check that a video bitrate lowered to fit `size` bytes is still worth encoding at.
+pub fn check_video_bitrate(size: u64, bitrate: u64) -> Result<u64, failure::Error> {
+    if bitrate < MIN_VIDEO_BITRATE {
+        bail!(
+            "target size {} is too small, it would need a video bitrate of {}k",
+            format(size),
+            bitrate / 1000
+        );
+    }
+
+    Ok(bitrate)
+}
@@ -0,0 +1,137 @@
+use crate::ffprobe::{MediaInfo, Rational};
+use std::fmt;
+
+/// Bounds that the resolution of a format must fit within.
+#[derive(Debug, Clone, Copy)]
+pub enum Bounds {
+    /// Fit the long and the short edge respectively, regardless of orientation.
+    Edges(u32, u32),
+    /// Fit within the given width.
+    Width(u32),
+}
+
+impl fmt::Display for Bounds {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bounds::Edges(long, short) => write!(fmt, "{}x{}", long, short),
+            Bounds::Width(width) => write!(fmt, "{}px wide", width),
+        }
+    }
+}
+
+/// The maximum resolution and frame rate of a format.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    /// The bounds the resolution must fit within.
+    pub bounds: Bounds,
+    /// The highest frame rate.
+    pub fps: Rational,
+}
+
+/// The resolution and frame rate decided for a video stream, based on the source.
+///
+/// The source is never upscaled and its frame rate is only ever reduced.
+#[derive(Debug, Clone)]
+pub struct VideoTarget {
+    /// The limits the target was decided from.
+    pub limits: Limits,
+    /// Resolution of the source, if known.
+    pub source_size: Option<(u32, u32)>,
+    /// Resolution to scale to, or `None` to keep the source resolution.
+    pub size: Option<(u32, u32)>,
+    /// Frame rate of the source, if known.
+    pub source_fps: Option<Rational>,
+    /// Frame rate to convert to, or `None` to keep the source frame rate.
+    pub fps: Option<Rational>,
+}
+
+impl VideoTarget {
+    /// Decide the target for the primary video stream of the source.
+    pub fn new(limits: Limits, info: &MediaInfo) -> VideoTarget {
+        let video = info.video();
+
+        let source_size = video.and_then(|v| match (v.width, v.height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => Some((width, height)),
+            _ => None,
+        });
+
+        let source_fps = video.and_then(|v| v.frame_rate);
+
+        let size = source_size.and_then(|(width, height)| fit(width, height, limits.bounds));
+
+        let fps = match source_fps {
+            Some(fps) if fps.as_f64() > limits.fps.as_f64() => Some(limits.fps),
+            _ => None,
+        };
+
+        VideoTarget {
+            limits,
+            source_size,
+            size,
+            source_fps,
+            fps,
+        }
+    }
+}
+
+impl VideoTarget {
+    /// The filter converting to the target frame rate, if needed.
+    pub fn fps_filter(&self) -> Option<String> {
+        self.fps.map(|fps| match fps.den {
+            1 => format!("fps={}", fps.num),
+            den => format!("fps={}/{}", fps.num, den),
+        })
+    }
+}
+
+impl fmt::Display for VideoTarget {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match (self.source_size, self.size) {
+            (Some((sw, sh)), Some((w, h))) => write!(
+                fmt,
+                "{}x{} -> {}x{} (capped to {})",
+                sw, sh, w, h, self.limits.bounds
+            )?,
+            (Some((sw, sh)), None) => write!(fmt, "{}x{} (source)", sw, sh)?,
+            (None, _) => write!(fmt, "unknown resolution (source)")?,
+        }
+
+        match (self.source_fps, self.fps) {
+            (Some(source), Some(fps)) => write!(fmt, ", {} -> {} fps (capped)", source, fps),
+            (Some(source), None) => write!(fmt, ", {} fps (source)", source),
+            (None, _) => write!(fmt, ", unknown fps (source)"),
+        }
+    }
+}
+
+/// Fit the given resolution within bounds, keeping the aspect ratio.
+///
+/// Returns `None` if the resolution already fits.
+fn fit(width: u32, height: u32, bounds: Bounds) -> Option<(u32, u32)> {
+    let factor = match bounds {
+        Bounds::Edges(long, short) => {
+            let (l, s) = if width >= height {
+                (width, height)
+            } else {
+                (height, width)
+            };
+
+            f64::min(
+                f64::from(long) / f64::from(l),
+                f64::from(short) / f64::from(s),
+            )
+        }
+        Bounds::Width(max) => f64::from(max) / f64::from(width),
+    };
+
+    if factor >= 1.0 {
+        return None;
+    }
+
+    Some((even(width, factor), even(height, factor)))
+}
+
+/// Scale a dimension and round it to an even number, as required by most encoders.
+fn even(value: u32, factor: f64) -> u32 {
+    u32::max(2, ((f64::from(value) * factor / 2.0).round() as u32) * 2)
+}
@@ -0,0 +1,132 @@
+//! Temporary files for writing outputs atomically.
+
+use failure::format_err;
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// A temporary file which is renamed into place once it is complete.
+///
+/// It lives in the same directory as the final output so that the rename is atomic, and keeps the
+/// extension of the output since ffmpeg uses it to pick a muxer. It is removed when dropped unless
+/// persisted.
+pub struct TempOutput {
+    temp: PathBuf,
+    output: PathBuf,
+    persisted: bool,
+}
+
+impl TempOutput {
+    /// Construct a temporary file for the given output.
+    pub fn new(output: &Path) -> Result<TempOutput, failure::Error> {
+        let name = output
+            .file_name()
+            .ok_or_else(|| format_err!("output has no file name: {}", output.display()))?
+            .to_string_lossy();
+
+        let ext = output
+            .extension()
+            .map(|e| format!(".{}", e.to_string_lossy()))
+            .unwrap_or_default();
+
+        let temp = output.with_file_name(format!(
+            ".{}.tessie-{}.tmp{}",
+            name.strip_suffix(&ext).unwrap_or(&name),
+            process::id(),
+            ext
+        ));
+
+        Ok(TempOutput {
+            temp,
+            output: output.to_owned(),
+            persisted: false,
+        })
+    }
+
+    /// The path of the temporary file.
+    pub fn path(&self) -> &Path {
+        &self.temp
+    }
+
+    /// Move the temporary file into place, replacing any existing output.
+    pub fn persist(mut self) -> Result<(), failure::Error> {
+        fs::rename(&self.temp, &self.output).map_err(|e| {
+            format_err!(
+                "failed to move {} to {}: {}",
+                self.temp.display(),
+                self.output.display(),
+                e
+            )
+        })?;
+
+        self.persisted = true;
+        Ok(())
+    }
+}
+
+impl Drop for TempOutput {
+    fn drop(&mut self) {
+        if !self.persisted {
+            let _ = fs::remove_file(&self.temp);
+        }
+    }
+}
+
+/// A temporary directory for intermediate files, like the statistics shared between the passes
+/// of a multi-pass encode.
+///
+/// Everything in it is removed with the directory when dropped.
+pub struct TempDir {
+    dir: PathBuf,
+}
+
+impl TempDir {
+    /// Create a new temporary directory.
+    ///
+    /// The directory is created fresh and only accessible by the current user, so a name which
+    /// is already taken, by a directory or a link planted by someone else, is never reused.
+    pub fn new() -> Result<TempDir, failure::Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        loop {
+            let dir = env::temp_dir().join(format!(
+                "tessie-{}-{}",
+                process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+
+            match create_private_dir(&dir) {
+                Ok(()) => return Ok(TempDir { dir }),
+                Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(format_err!("failed to create {}: {}", dir.display(), e)),
+            }
+        }
+    }
+
+    /// The path of a file in the directory.
+    pub fn join(&self, name: &str) -> PathBuf {
+        self.dir.join(name)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// Create a single directory which only the current user can access.
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt as _;
+        builder.mode(0o700);
+    }
+
+    builder.create(dir)
+}
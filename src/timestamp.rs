@@ -0,0 +1,110 @@
+//! Parsing and formatting of ffmpeg time durations.
+
+use failure::{bail, format_err};
+
+/// Parse a time duration in any of the forms accepted by ffmpeg into seconds.
+///
+/// This is either `[-][HH:]MM:SS[.m...]` or `[-]S+[.m...][s|ms|us]`.
+pub fn parse(s: &str) -> Result<f64, failure::Error> {
+    let err = || format_err!("illegal timestamp: {}", s);
+
+    let (negative, rest) = match s.trim().strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s.trim()),
+    };
+
+    if rest.is_empty() {
+        return Err(err());
+    }
+
+    let seconds = if rest.contains(':') {
+        let parts = rest.split(':').collect::<Vec<_>>();
+
+        let (h, m, s) = match parts[..] {
+            [m, s] => ("0", m, s),
+            [h, m, s] => (h, m, s),
+            _ => return Err(err()),
+        };
+
+        let h = h.parse::<u64>().map_err(|_| err())?;
+        let m = m.parse::<u64>().map_err(|_| err())?;
+        let s = s.parse::<f64>().map_err(|_| err())?;
+
+        if m >= 60 || !(0.0..60.0).contains(&s) {
+            return Err(err());
+        }
+
+        (h * 3600 + m * 60) as f64 + s
+    } else {
+        let (value, scale) = if let Some(value) = rest.strip_suffix("ms") {
+            (value, 1e-3)
+        } else if let Some(value) = rest.strip_suffix("us") {
+            (value, 1e-6)
+        } else if let Some(value) = rest.strip_suffix('s') {
+            (value, 1.0)
+        } else {
+            (rest, 1.0)
+        };
+
+        if !value.chars().all(|c| c.is_ascii_digit() || c == '.') {
+            bail!("illegal timestamp: {}", s);
+        }
+
+        value.parse::<f64>().map_err(|_| err())? * scale
+    };
+
+    Ok(if negative { -seconds } else { seconds })
+}
+
+/// Format a duration in seconds as `HH:MM:SS.ss`.
+pub fn format(seconds: f64) -> String {
+    let seconds = seconds.max(0.0);
+    let whole = seconds.trunc() as u64;
+    let fract = seconds - whole as f64;
+
+    format!(
+        "{:02}:{:02}:{:05.2}",
+        whole / 3600,
+        (whole / 60) % 60,
+        (whole % 60) as f64 + fract
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{format, parse};
+
+    #[test]
+    fn parses_clock_times() {
+        assert_eq!(parse("01:02:03.5").unwrap(), 3723.5);
+        assert_eq!(parse("02:03").unwrap(), 123.0);
+        assert_eq!(parse("-00:00:01.25").unwrap(), -1.25);
+    }
+
+    #[test]
+    fn parses_seconds() {
+        assert_eq!(parse("90").unwrap(), 90.0);
+        assert_eq!(parse("1.5").unwrap(), 1.5);
+        assert_eq!(parse("2s").unwrap(), 2.0);
+        assert_eq!(parse("250ms").unwrap(), 0.25);
+        assert_eq!(parse("500us").unwrap(), 0.0005);
+    }
+
+    #[test]
+    fn rejects_invalid() {
+        for s in &[
+            "", "-", "1:2:3:4", "00:60:00", "00:00:60", "1m", "ab", "1.2.3",
+        ] {
+            assert_eq!(
+                parse(s).unwrap_err().to_string(),
+                format!("illegal timestamp: {}", s)
+            );
+        }
+    }
+
+    #[test]
+    fn formats() {
+        assert_eq!(format(3723.5), "01:02:03.50");
+        assert_eq!(format(-1.0), "00:00:00.00");
+    }
+}
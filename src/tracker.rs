@@ -0,0 +1,128 @@
+//! Estimating and rendering how far along a transcode is.
+
+use std::time::Instant;
+use tessie::{timestamp, Progress};
+
+/// Estimates how far along a transcode is.
+pub struct Tracker {
+    /// The expected duration of the output, if known.
+    duration: Option<f64>,
+    started: Instant,
+}
+
+/// An estimate of how far along a transcode is.
+#[derive(Debug, Clone, Copy)]
+pub struct Estimate {
+    /// How done the transcode is, from 0 to 1.
+    pub ratio: f64,
+    /// Estimated remaining time in seconds.
+    pub eta: Option<f64>,
+}
+
+impl Tracker {
+    /// Construct a new tracker for an output of the given expected duration.
+    pub fn new(duration: Option<f64>) -> Tracker {
+        Tracker {
+            duration: duration.filter(|d| *d > 0.0),
+            started: Instant::now(),
+        }
+    }
+
+    /// The expected duration of the output, if known.
+    pub fn duration(&self) -> Option<f64> {
+        self.duration
+    }
+
+    /// Estimate how far along we are, if the expected duration is known.
+    ///
+    /// Every pass of a multi-pass encode goes through the whole output, so the estimate covers
+    /// all of them. Uses the speed reported by ffmpeg if available, otherwise the elapsed wall
+    /// time.
+    pub fn estimate(&self, progress: &Progress) -> Option<Estimate> {
+        let duration = self.duration?;
+        let (pass, passes) = progress.pass.unwrap_or((1, 1));
+
+        if progress.done && pass >= passes {
+            return Some(Estimate {
+                ratio: 1.0,
+                eta: Some(0.0),
+            });
+        }
+
+        let out_time = if progress.done {
+            duration
+        } else {
+            progress.out_time.unwrap_or_default().min(duration)
+        };
+
+        let total = duration * f64::from(passes);
+        let processed = duration * f64::from(pass.saturating_sub(1)) + out_time;
+        let remaining = (total - processed).max(0.0);
+
+        let eta = match progress.speed {
+            Some(speed) if speed > 0.0 => Some(remaining / speed),
+            _ if processed > 0.0 => {
+                let elapsed = self.started.elapsed().as_secs_f64();
+                Some(elapsed * remaining / processed)
+            }
+            _ => None,
+        };
+
+        Some(Estimate {
+            ratio: (processed / total).clamp(0.0, 1.0),
+            eta,
+        })
+    }
+}
+
+/// Render a single line of progress, with a bar if the expected duration is known.
+pub fn render(tracker: &Tracker, progress: &Progress) -> String {
+    const WIDTH: usize = 30;
+
+    let out_time = progress.out_time.unwrap_or_default();
+    let mut parts = Vec::new();
+
+    if let Some((pass, passes)) = progress.pass {
+        parts.push(format!("pass {}/{}", pass, passes));
+    }
+
+    match (tracker.duration(), tracker.estimate(progress)) {
+        (Some(duration), Some(estimate)) => {
+            let filled = (estimate.ratio * WIDTH as f64).round() as usize;
+
+            parts.push(format!(
+                "[{}{}] {:5.1}%",
+                "#".repeat(filled),
+                " ".repeat(WIDTH - filled),
+                estimate.ratio * 100.0
+            ));
+
+            parts.push(format!(
+                "{} / {}",
+                timestamp::format(out_time.min(duration)),
+                timestamp::format(duration)
+            ));
+
+            if let Some(eta) = estimate.eta {
+                parts.push(format!("ETA {}", timestamp::format(eta)));
+            }
+        }
+        _ => {
+            parts.push(timestamp::format(out_time));
+        }
+    }
+
+    if let Some(fps) = progress.fps {
+        parts.push(format!("{:.0} fps", fps));
+    }
+
+    if let Some(speed) = progress.speed {
+        parts.push(format!("{:.2}x", speed));
+    }
+
+    if let Some(total_size) = progress.total_size {
+        parts.push(format!("{:.1} MiB", total_size as f64 / (1024.0 * 1024.0)));
+    }
+
+    parts.join("  ")
+}
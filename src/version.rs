@@ -0,0 +1,91 @@
+//! Versions of ffmpeg, as printed in the banner of `ffmpeg -version`.
+
+use failure::{bail, format_err};
+use std::{fmt, str};
+
+/// A release version of ffmpeg.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    /// The major version, like `6` in `6.1.2`.
+    pub major: u32,
+    /// The minor version, like `1` in `6.1.2`.
+    pub minor: u32,
+    /// The patch version, like `2` in `6.1.2`, which is `0` if it was left out.
+    pub patch: u32,
+}
+
+impl Version {
+    /// The oldest version of ffmpeg that tessie works with.
+    pub const MINIMUM: Version = Version {
+        major: 4,
+        minor: 0,
+        patch: 0,
+    };
+
+    /// Parse the version from the banner printed by `ffmpeg -version`.
+    ///
+    /// The banner looks like `ffmpeg version 6.1.1-static Copyright ...`. Builds from git print
+    /// something like `ffmpeg version N-112345-g1234abcd` instead, which has no release version,
+    /// so `None` is returned for them.
+    pub fn from_banner(banner: &str) -> Option<Version> {
+        let version = banner
+            .lines()
+            .next()?
+            .split_whitespace()
+            .skip_while(|w| *w != "version")
+            .nth(1)?;
+
+        // Tagged builds are prefixed with `n`, like `n6.1`.
+        let version = version.strip_prefix('n').unwrap_or(version);
+
+        let end = version
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(version.len());
+
+        version[..end].trim_end_matches('.').parse().ok()
+    }
+}
+
+impl str::FromStr for Version {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.split('.');
+
+        let mut part = |name| match parts.next() {
+            Some(part) => part
+                .parse::<u32>()
+                .map_err(|_| format_err!("illegal version `{}`: bad {} version", s, name)),
+            None => Ok(0),
+        };
+
+        let major = part("major")?;
+        let minor = part("minor")?;
+        let patch = part("patch")?;
+
+        if parts.next().is_some() {
+            bail!(
+                "illegal version `{}`: expected `<major>[.<minor>[.<patch>]]`",
+                s
+            );
+        }
+
+        Ok(Version {
+            major,
+            minor,
+            patch,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        write!(fmt, "{}.{}", self.major, self.minor)?;
+
+        if self.patch != 0 {
+            write!(fmt, ".{}", self.patch)?;
+        }
+
+        Ok(())
+    }
+}
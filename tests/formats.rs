@@ -0,0 +1,902 @@
+//! Golden tests for the arguments every built-in format passes to ffmpeg.
+
+mod support;
+
+use std::{path::PathBuf, sync::Arc};
+use support::{source, strings, Fake, LARGE, SMALL};
+use tessie::{Backend, Builtin, Config, Ffmpeg, Format, Settings, TranscodeJob};
+
+const BACKENDS: [Backend; 4] = [
+    Backend::Nvidia,
+    Backend::Vaapi,
+    Backend::Qsv,
+    Backend::Software,
+];
+
+/// The arguments of a job transcoding `in.mkv` from the given source.
+fn argv(builtin: Builtin, backend: Backend, json: &str) -> Vec<String> {
+    let job = TranscodeJob::new(Format::builtin(builtin, backend), "in.mkv", "out.mp4")
+        .source(&source(json));
+
+    strings(&job.argv())
+}
+
+/// Build the expected arguments from the parts of a command.
+fn expected(parts: &[&[&str]]) -> Vec<String> {
+    parts
+        .iter()
+        .flat_map(|p| p.iter())
+        .map(|a| a.to_string())
+        .collect()
+}
+
+const PREFIX: &[&str] = &["-hide_banner", "-loglevel", "warning", "-y"];
+
+const YOUTUBE_AUDIO: &[&str] = &["-c:a", "aac", "-profile:a", "aac_low", "-b:a", "384k"];
+
+const NVENC: &[&str] = &[
+    "-c:v",
+    "h264_nvenc",
+    "-coder",
+    "1",
+    "-preset",
+    "llhq",
+    "-rc:v",
+    "vbr_minqp",
+    "-qmin:v",
+    "21",
+    "-qmax:v",
+    "23",
+    "-b:v",
+    "5000k",
+    "-maxrate:v",
+    "8000k",
+    "-profile:v",
+    "high",
+    "-bf",
+    "2",
+];
+
+const VAAPI: &[&str] = &[
+    "-c:v",
+    "h264_vaapi",
+    "-b:v",
+    "5000k",
+    "-maxrate:v",
+    "8000k",
+    "-profile:v",
+    "high",
+    "-bf",
+    "2",
+];
+
+const QSV: &[&str] = &[
+    "-c:v",
+    "h264_qsv",
+    "-preset",
+    "slow",
+    "-b:v",
+    "5000k",
+    "-maxrate:v",
+    "8000k",
+    "-profile:v",
+    "high",
+    "-bf",
+    "2",
+];
+
+const X264: &[&str] = &[
+    "-c:v",
+    "libx264",
+    "-preset",
+    "slow",
+    "-crf",
+    "22",
+    "-maxrate:v",
+    "8000k",
+    "-bufsize:v",
+    "16000k",
+    "-profile:v",
+    "high",
+    "-pix_fmt",
+    "yuv420p",
+    "-bf",
+    "2",
+];
+
+const MP4: &[&str] = &["-f", "mp4", "out.mp4"];
+
+#[test]
+fn youtube_nvidia() {
+    let input = &["-hwaccel", "cuvid", "-c:v", "h264_cuvid", "-i", "in.mkv"][..];
+
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Nvidia, LARGE),
+        expected(&[
+            PREFIX,
+            input,
+            &["-vf", "scale=1920:1080,fps=60"],
+            NVENC,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Nvidia, SMALL),
+        expected(&[PREFIX, input, NVENC, YOUTUBE_AUDIO, MP4])
+    );
+}
+
+#[test]
+fn youtube_vaapi() {
+    let input = &[
+        "-hwaccel",
+        "vaapi",
+        "-hwaccel_device",
+        "/dev/dri/renderD128",
+        "-hwaccel_output_format",
+        "vaapi",
+        "-i",
+        "in.mkv",
+    ][..];
+
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Vaapi, LARGE),
+        expected(&[
+            PREFIX,
+            input,
+            &[
+                "-vf",
+                "format=nv12|vaapi,hwupload,scale_vaapi=w=1920:h=1080,fps=60"
+            ],
+            VAAPI,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+
+    // Frames are uploaded even if they don't need to be scaled.
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Vaapi, SMALL),
+        expected(&[
+            PREFIX,
+            input,
+            &["-vf", "format=nv12|vaapi,hwupload"],
+            VAAPI,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+}
+
+#[test]
+fn youtube_qsv() {
+    let input = &["-hwaccel", "qsv", "-c:v", "h264_qsv", "-i", "in.mkv"][..];
+
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Qsv, LARGE),
+        expected(&[
+            PREFIX,
+            input,
+            &["-vf", "scale_qsv=w=1920:h=1080,fps=60"],
+            QSV,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Qsv, SMALL),
+        expected(&[PREFIX, input, QSV, YOUTUBE_AUDIO, MP4])
+    );
+}
+
+#[test]
+fn youtube_software() {
+    let input = &["-i", "in.mkv"][..];
+
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Software, LARGE),
+        expected(&[
+            PREFIX,
+            input,
+            &["-vf", "scale=1920:1080,fps=60"],
+            X264,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Software, SMALL),
+        expected(&[PREFIX, input, X264, YOUTUBE_AUDIO, MP4])
+    );
+}
+
+/// A 1080p HEVC source, which hardware backends decode with other decoders than H.264.
+const HEVC: &str = r#"{
+    "format": {"format_name": "matroska,webm", "duration": "60.000000"},
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "hevc", "width": 1920, "height": 1080, "avg_frame_rate": "30/1"},
+        {"index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000"}
+    ]
+}"#;
+
+/// A 4K ProRes source, which no hardware backend can decode.
+const PRORES: &str = r#"{
+    "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "60.000000"},
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "prores", "width": 3840, "height": 2160, "avg_frame_rate": "30/1"},
+        {"index": 1, "codec_type": "audio", "codec_name": "pcm_s16le", "channels": 2, "sample_rate": "48000"}
+    ]
+}"#;
+
+#[test]
+fn youtube_hevc_nvidia() {
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Nvidia, HEVC),
+        expected(&[
+            PREFIX,
+            &["-hwaccel", "cuvid", "-c:v", "hevc_cuvid", "-i", "in.mkv"],
+            NVENC,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+}
+
+#[test]
+fn youtube_hevc_vaapi() {
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Vaapi, HEVC),
+        expected(&[
+            PREFIX,
+            &[
+                "-hwaccel",
+                "vaapi",
+                "-hwaccel_device",
+                "/dev/dri/renderD128",
+                "-hwaccel_output_format",
+                "vaapi",
+                "-i",
+                "in.mkv",
+            ],
+            &["-vf", "format=nv12|vaapi,hwupload"],
+            VAAPI,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+}
+
+#[test]
+fn youtube_hevc_qsv() {
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Qsv, HEVC),
+        expected(&[
+            PREFIX,
+            &["-hwaccel", "qsv", "-c:v", "hevc_qsv", "-i", "in.mkv"],
+            QSV,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+}
+
+#[test]
+fn youtube_decodes_unsupported_codecs_in_software() {
+    let input = &["-i", "in.mkv"][..];
+
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Nvidia, PRORES),
+        expected(&[
+            PREFIX,
+            input,
+            &["-vf", "scale=1920:1080"],
+            NVENC,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+
+    // Frames decoded in software are scaled in software too.
+    assert_eq!(
+        argv(Builtin::YouTube, Backend::Qsv, PRORES),
+        expected(&[
+            PREFIX,
+            input,
+            &["-vf", "scale=1920:1080"],
+            QSV,
+            YOUTUBE_AUDIO,
+            MP4
+        ])
+    );
+}
+
+#[test]
+fn youtube_decodes_in_software_without_decoder() {
+    // The build has the decoder for H.264, but not for HEVC.
+    let fake = Fake {
+        codecs: vec!["h264_nvenc", "h264_cuvid", "aac", "scale", "fps"],
+        hwaccels: vec!["cuda"],
+        ..Fake::default()
+    };
+    let ffmpeg = Ffmpeg::with_runner(Arc::new(fake), "ffmpeg", Some(Backend::Nvidia)).unwrap();
+
+    let mut format = Format::builtin(Builtin::YouTube, Backend::Nvidia);
+    format.adapt(ffmpeg.capabilities());
+
+    let job = TranscodeJob::new(format.clone(), "in.mkv", "out.mp4").source(&source(HEVC));
+
+    assert_eq!(
+        strings(&job.argv()),
+        expected(&[PREFIX, &["-i", "in.mkv"], NVENC, YOUTUBE_AUDIO, MP4])
+    );
+    ffmpeg.check(job.format()).unwrap();
+
+    // The decoder which is picked is required.
+    let job = TranscodeJob::new(format, "in.mkv", "out.mp4").source(&source(SMALL));
+    assert_eq!(job.format().requirements().decoders, ["h264_cuvid"]);
+    assert!(ffmpeg.check(job.format()).is_ok());
+}
+
+#[test]
+fn youtube_without_source() {
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        "out.mp4",
+    );
+
+    assert_eq!(
+        strings(&job.argv()),
+        expected(&[PREFIX, &["-i", "in.mkv"], X264, YOUTUBE_AUDIO, MP4])
+    );
+}
+
+#[test]
+fn gif() {
+    let filter = "[0:v] fps=12,scale=280:158,split [a][b];[a] palettegen [p];[b][p] paletteuse";
+
+    for backend in BACKENDS {
+        for json in [LARGE, SMALL] {
+            assert_eq!(
+                argv(Builtin::Gif, backend, json),
+                expected(&[
+                    PREFIX,
+                    &["-i", "in.mkv", "-filter_complex", filter],
+                    &["-f", "gif", "out.mp4"]
+                ]),
+                "backend: {}",
+                backend
+            );
+        }
+    }
+}
+
+#[test]
+fn copy() {
+    for backend in BACKENDS {
+        for json in [LARGE, SMALL] {
+            assert_eq!(
+                argv(Builtin::Copy, backend, json),
+                expected(&[
+                    PREFIX,
+                    &["-i", "in.mkv", "-c:v", "copy", "-c:a", "copy", "out.mp4"]
+                ]),
+                "backend: {}",
+                backend
+            );
+        }
+    }
+}
+
+#[test]
+fn webm() {
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::WebM, Backend::Nvidia),
+        "in.mkv",
+        "out.webm",
+    )
+    .source(&source(LARGE));
+
+    let encoder = &[
+        "-vf",
+        "scale=1920:1080,fps=60",
+        "-c:v",
+        "libvpx-vp9",
+        "-deadline",
+        "good",
+        "-cpu-used",
+        "2",
+        "-row-mt",
+        "1",
+        "-crf",
+        "31",
+        "-b:v",
+        "0",
+        "-pix_fmt",
+        "yuv420p",
+    ][..];
+
+    let audio = &["-c:a", "libopus", "-b:a", "128k"][..];
+    let pass = |n| ["-pass", n, "-passlogfile", "out.webm-passlog"];
+
+    assert_eq!(job.passes(), 2);
+
+    let passes = job
+        .pass_argv()
+        .iter()
+        .map(|a| strings(a))
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        passes,
+        vec![
+            expected(&[
+                PREFIX,
+                &["-i", "in.mkv"],
+                encoder,
+                audio,
+                &["-f", "webm"],
+                &pass("1"),
+                &["-an", "-f", "null", "-"]
+            ]),
+            expected(&[
+                PREFIX,
+                &["-i", "in.mkv"],
+                encoder,
+                audio,
+                &["-f", "webm"],
+                &pass("2"),
+                &["out.webm"]
+            ]),
+        ]
+    );
+
+    assert_eq!(strings(&job.argv()), passes[1]);
+}
+
+#[test]
+fn av1() {
+    let svt = &["-c:v", "libsvtav1", "-preset", "8", "-crf", "32"][..];
+    let rest = &[
+        "-pix_fmt", "yuv420p", "-c:a", "libopus", "-b:a", "128k", "-f", "matroska", "out.mp4",
+    ][..];
+
+    assert_eq!(
+        argv(Builtin::Av1, Backend::Software, SMALL),
+        expected(&[PREFIX, &["-i", "in.mkv"], svt, rest])
+    );
+
+    // Builds without SVT-AV1 fall back to libaom.
+    let mut format = Format::builtin(Builtin::Av1, Backend::Software);
+    let fake = Fake {
+        codecs: vec!["libaom-av1"],
+        ..Fake::default()
+    };
+    let ffmpeg = Ffmpeg::with_runner(Arc::new(fake), "ffmpeg", Some(Backend::Software)).unwrap();
+    format.adapt(ffmpeg.capabilities());
+
+    let job = TranscodeJob::new(format, "in.mkv", "out.mp4").source(&source(SMALL));
+
+    assert_eq!(
+        strings(&job.argv()),
+        expected(&[
+            PREFIX,
+            &["-i", "in.mkv"],
+            &[
+                "-c:v",
+                "libaom-av1",
+                "-cpu-used",
+                "4",
+                "-row-mt",
+                "1",
+                "-crf",
+                "32",
+                "-b:v",
+                "0"
+            ],
+            rest
+        ])
+    );
+}
+
+/// A 10-bit 1080p source with two audio tracks and subtitles.
+const DEEP: &str = r#"{
+    "format": {"format_name": "matroska,webm", "duration": "60.000000"},
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "hevc", "width": 1920, "height": 1080, "pix_fmt": "yuv420p10le", "avg_frame_rate": "60/1"},
+        {"index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000"},
+        {"index": 2, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "48000"},
+        {"index": 3, "codec_type": "subtitle", "codec_name": "subrip"}
+    ]
+}"#;
+
+#[test]
+fn archive() {
+    let input = &["-i", "in.mkv", "-map", "0:v:0", "-map", "0:1"][..];
+    let x265 = &["-c:v", "libx265", "-preset", "slow", "-crf", "22"][..];
+    let rest = &[
+        "-tag:v", "hvc1", "-c:a", "copy", "-c:s", "mov_text", "-f", "mp4",
+    ][..];
+    let output = &["out.mp4"][..];
+
+    // Sources are never scaled, and keep their bit depth.
+    assert_eq!(
+        argv(Builtin::Archive, Backend::Software, LARGE),
+        expected(&[PREFIX, input, x265, &["-pix_fmt", "yuv420p"], rest, output])
+    );
+
+    let input = &[
+        "-i", "in.mkv", "-map", "0:v:0", "-map", "0:1", "-map", "0:2", "-map", "0:3",
+    ][..];
+
+    assert_eq!(
+        argv(Builtin::Archive, Backend::Software, DEEP),
+        expected(&[
+            PREFIX,
+            input,
+            x265,
+            &["-pix_fmt", "yuv420p10le"],
+            rest,
+            output
+        ])
+    );
+
+    let nvenc = &[
+        "-c:v",
+        "hevc_nvenc",
+        "-preset",
+        "slow",
+        "-rc:v",
+        "vbr",
+        "-cq:v",
+        "24",
+        "-b:v",
+        "0",
+        "-pix_fmt",
+        "p010le",
+    ][..];
+
+    assert_eq!(
+        argv(Builtin::Archive, Backend::Nvidia, DEEP),
+        expected(&[PREFIX, input, nvenc, rest, output])
+    );
+
+    // Builds without hevc_nvenc fall back to libx265.
+    let fake = Fake {
+        codecs: vec!["h264_nvenc", "h264_cuvid", "libx265", "mov_text"],
+        ..Fake::default()
+    };
+    let ffmpeg = Ffmpeg::with_runner(Arc::new(fake), "ffmpeg", Some(Backend::Nvidia)).unwrap();
+
+    let mut format = Format::builtin(Builtin::Archive, Backend::Nvidia);
+    format.adapt(ffmpeg.capabilities());
+    let job = TranscodeJob::new(format, "in.mkv", "out.mp4").source(&source(DEEP));
+
+    assert_eq!(
+        strings(&job.argv()),
+        expected(&[
+            PREFIX,
+            input,
+            x265,
+            &["-pix_fmt", "yuv420p10le"],
+            rest,
+            output
+        ])
+    );
+    ffmpeg.check(job.format()).unwrap();
+
+    // An explicit pixel format and mapped tracks take priority.
+    let mut format = Format::builtin(Builtin::Archive, Backend::Software);
+    format.settings.set("video.pix_fmt=yuv444p").unwrap();
+
+    let job = TranscodeJob::new(format, "in.mkv", "out.mp4")
+        .source(&source(DEEP))
+        .map("0:0")
+        .map("0:2");
+
+    assert_eq!(
+        strings(&job.argv()),
+        expected(&[
+            PREFIX,
+            &["-i", "in.mkv", "-map", "0:0", "-map", "0:2"],
+            x265,
+            &["-pix_fmt", "yuv444p"],
+            rest,
+            output
+        ])
+    );
+}
+
+/// A Blu-ray rip with PCM, TrueHD and FLAC audio, and both bitmap and text subtitles.
+const DISC: &str = r#"{
+    "format": {"format_name": "matroska,webm", "duration": "60.000000"},
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080, "pix_fmt": "yuv420p", "avg_frame_rate": "24000/1001"},
+        {"index": 1, "codec_type": "audio", "codec_name": "pcm_s16le", "channels": 2, "sample_rate": "48000"},
+        {"index": 2, "codec_type": "audio", "codec_name": "ac3", "channels": 6, "sample_rate": "48000"},
+        {"index": 3, "codec_type": "audio", "codec_name": "truehd", "channels": 8, "sample_rate": "48000"},
+        {"index": 4, "codec_type": "audio", "codec_name": "flac", "channels": 2, "sample_rate": "48000"},
+        {"index": 5, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle"},
+        {"index": 6, "codec_type": "subtitle", "codec_name": "subrip"}
+    ]
+}"#;
+
+#[test]
+fn archive_streams() {
+    // Audio mp4 can't hold is transcoded while FLAC is copied, and bitmap subtitles are dropped.
+    assert_eq!(
+        argv(Builtin::Archive, Backend::Software, DISC),
+        expected(&[
+            PREFIX,
+            &[
+                "-i", "in.mkv", "-map", "0:v:0", "-map", "0:1", "-map", "0:2", "-map", "0:3",
+                "-map", "0:4", "-map", "0:6",
+            ],
+            &["-c:v", "libx265", "-preset", "slow", "-crf", "22"],
+            &["-pix_fmt", "yuv420p"],
+            &["-tag:v", "hvc1", "-c:a", "copy", "-c:s", "mov_text", "-f", "mp4",],
+            &["-c:a:0", "aac", "-b:a:0", "384k", "-c:a:2", "aac", "-b:a:2", "384k",],
+            &["out.mp4"]
+        ])
+    );
+
+    // Settings which encode audio differently are left alone.
+    let mut format = Format::builtin(Builtin::Archive, Backend::Software);
+    format.settings.set("audio.codec=aac").unwrap();
+
+    let job = TranscodeJob::new(format, "in.mkv", "out.mp4").source(&source(DISC));
+
+    assert_eq!(
+        strings(&job.argv())[PREFIX.len()..PREFIX.len() + 8],
+        ["-i", "in.mkv", "-map", "0:v:0", "-map", "0:a?", "-map", "0:s?"]
+    );
+}
+
+#[test]
+fn merged_presets_are_validated() {
+    let mut config = Config::default();
+
+    let complex = Settings {
+        extends: Some(String::from("Copy")),
+        filter_complex: Some(String::from("[0:v] null")),
+        ..Settings::default()
+    };
+
+    let filtered = Settings {
+        extends: Some(String::from("Complex")),
+        video_filters: Some(vec![String::from("fps=30")]),
+        ..Settings::default()
+    };
+
+    for (name, preset) in [("Complex", complex), ("Filtered", filtered)] {
+        config.presets.insert(name.to_string(), preset);
+        config
+            .paths
+            .insert(name.to_string(), PathBuf::from("tessie.toml"));
+    }
+
+    Format::parse("Complex", &config, Backend::Software).unwrap();
+
+    let error = Format::parse("Filtered", &config, Backend::Software).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid config: tessie.toml: presets.Filtered (merged with what it extends): \
+         filter_complex: can't be combined with video_filters or audio_filters"
+    );
+
+    config.presets.get_mut("Complex").unwrap().extends = Some(String::from("Nope"));
+
+    let error = Format::parse("Filtered", &config, Backend::Software).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "invalid config: tessie.toml: presets.Complex.extends: no format named `Nope`"
+    );
+}
+
+#[test]
+fn opt_in_passes() {
+    let mut format = Format::builtin(Builtin::YouTube, Backend::Software);
+    format.settings.set("passes=2").unwrap();
+
+    let job = TranscodeJob::new(format, "in.mkv", "out.mp4").source(&source(SMALL));
+    let passes = job
+        .pass_argv()
+        .iter()
+        .map(|a| strings(a))
+        .collect::<Vec<_>>();
+
+    let pass = |n| ["-pass", n, "-passlogfile", "out.mp4-passlog"];
+
+    assert_eq!(
+        passes,
+        vec![
+            expected(&[
+                PREFIX,
+                &["-i", "in.mkv"],
+                X264,
+                YOUTUBE_AUDIO,
+                &["-f", "mp4"],
+                &pass("1"),
+                &["-an", "-f", "null", "-"]
+            ]),
+            expected(&[
+                PREFIX,
+                &["-i", "in.mkv"],
+                X264,
+                YOUTUBE_AUDIO,
+                &["-f", "mp4"],
+                &pass("2"),
+                &["out.mp4"]
+            ]),
+        ]
+    );
+
+    let mut format = Format::builtin(Builtin::WebM, Backend::Software);
+    format.settings.set("passes=1").unwrap();
+    assert_eq!(format.passes(), 1);
+
+    let error = format.settings.set("passes=3").unwrap_err();
+    assert_eq!(error.to_string(), "passes: expected 1 or 2, but got `3`");
+}
+
+#[test]
+fn target_size() {
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        "out.mp4",
+    )
+    .source(&source(SMALL))
+    .target_size(tessie::size::parse("5M").unwrap());
+
+    // 5 MB over 10 seconds, less 3% overhead and 384k of audio.
+    assert_eq!(job.video_bitrate().unwrap(), Some(3_496_000));
+    assert_eq!(job.passes(), 2);
+
+    let video = &[
+        "-c:v",
+        "libx264",
+        "-preset",
+        "slow",
+        "-b:v",
+        "3496k",
+        "-maxrate:v",
+        "5244k",
+        "-bufsize:v",
+        "6992k",
+        "-profile:v",
+        "high",
+        "-pix_fmt",
+        "yuv420p",
+        "-bf",
+        "2",
+    ][..];
+
+    assert_eq!(
+        strings(&job.argv()),
+        expected(&[
+            PREFIX,
+            &["-i", "in.mkv"],
+            video,
+            YOUTUBE_AUDIO,
+            &["-f", "mp4", "-pass", "2", "-passlogfile", "out.mp4-passlog"],
+            &["out.mp4"]
+        ])
+    );
+
+    // The window of the transcode is what needs to fit.
+    let clip = job.clone().duration("5");
+    assert_eq!(clip.video_bitrate().unwrap(), Some(7_376_000));
+
+    let error = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        "out.mp4",
+    )
+    .target_size(5_000_000)
+    .video_bitrate()
+    .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "the duration of the output must be known to fit a target size"
+    );
+
+    let error = TranscodeJob::new(
+        Format::builtin(Builtin::Gif, Backend::Software),
+        "in.mkv",
+        "out.gif",
+    )
+    .source(&source(SMALL))
+    .target_size(5_000_000)
+    .video_bitrate()
+    .unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "format Gif doesn't encode video at a bitrate, so it can't fit a target size"
+    );
+
+    let error = job.target_size(100_000).video_bitrate().unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "target size 0.1 MB is too small for 10.0s of video with 384k of audio"
+    );
+}
+
+#[test]
+fn options() {
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::Copy, Backend::Software),
+        "in.mkv",
+        "out.mkv",
+    )
+    .source(&source(SMALL))
+    .start("1:00")
+    .end("2:00")
+    .duration("30")
+    .map("0:0")
+    .map("0:1")
+    .input_arg("-re")
+    .output_arg("-movflags")
+    .output_arg("+faststart")
+    .trailing_arg("-metadata")
+    .trailing_arg("title=Clip");
+
+    assert_eq!(
+        strings(&job.argv()),
+        expected(&[
+            PREFIX,
+            &["-ss", "1:00", "-to", "2:00", "-t", "30"],
+            &["-re", "-i", "in.mkv", "-map", "0:0", "-map", "0:1"],
+            &["-c:v", "copy", "-c:a", "copy"],
+            &["-movflags", "+faststart"],
+            &["-metadata", "title=Clip", "out.mkv"]
+        ])
+    );
+}
+
+#[test]
+fn overridden_settings() {
+    let mut format = Format::builtin(Builtin::YouTube, Backend::Software);
+    format.settings.set("video.crf=").unwrap();
+    format.settings.set("video.bitrate=8000k").unwrap();
+    format.settings.set("audio.bitrate=192k").unwrap();
+
+    let job = TranscodeJob::new(format, "in.mkv", "out.mp4").source(&source(SMALL));
+
+    assert_eq!(
+        strings(&job.argv()),
+        expected(&[
+            PREFIX,
+            &["-i", "in.mkv"],
+            &[
+                "-c:v",
+                "libx264",
+                "-preset",
+                "slow",
+                "-b:v",
+                "8000k",
+                "-maxrate:v",
+                "8000k"
+            ],
+            &[
+                "-bufsize:v",
+                "16000k",
+                "-profile:v",
+                "high",
+                "-pix_fmt",
+                "yuv420p",
+                "-bf",
+                "2"
+            ],
+            &["-c:a", "aac", "-profile:a", "aac_low", "-b:a", "192k"],
+            MP4
+        ])
+    );
+}
@@ -0,0 +1,622 @@
+//! Tests running ffmpeg through a fake runner.
+
+mod support;
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+    sync::Arc,
+};
+use support::{source, Fake, Recorder, SMALL};
+use tessie::{
+    Backend, Builtin, Cancel, Class, Events, Ffmpeg, Format, Metric, TranscodeError, TranscodeJob,
+};
+
+/// A fresh directory for the outputs of a single test.
+fn output_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("tessie-{}-{}", name, process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn detects_software_without_hardware() {
+    let fake = Arc::new(Fake::software());
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", None).unwrap();
+
+    assert_eq!(ffmpeg.backend(), Backend::Software);
+    ffmpeg
+        .check(&Format::builtin(Builtin::YouTube, Backend::Software))
+        .unwrap();
+
+    // Hardware is not test encoded unless ffmpeg was built with support for it.
+    assert!(fake.spawned().is_empty());
+    assert!(fake
+        .calls
+        .lock()
+        .unwrap()
+        .iter()
+        .all(|c| !c.iter().any(|a| a == "lavfi")));
+}
+
+#[test]
+fn missing_encoders_are_reported() {
+    let fake = Arc::new(Fake::default());
+    let ffmpeg = Ffmpeg::with_runner(fake, "ffmpeg", Some(Backend::Software)).unwrap();
+
+    let error = ffmpeg
+        .check(&Format::builtin(Builtin::YouTube, Backend::Software))
+        .unwrap_err();
+
+    assert!(error.to_string().contains("encoder `libx264`"), "{}", error);
+}
+
+#[test]
+fn transcode() {
+    let fake = Arc::new(Fake {
+        stdout: String::from(
+            "out_time=00:00:05.000000\nspeed=2.0x\nprogress=continue\n\
+             out_time=00:00:10.000000\nspeed=2.0x\nprogress=end\n",
+        ),
+        ..Fake::software()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+    let dir = output_dir("transcode");
+    let output = dir.join("out.mp4");
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        &output,
+    )
+    .source(&source(SMALL));
+
+    let events = Recorder::default();
+    let outcome = ffmpeg.transcode(&job, &events).unwrap();
+
+    assert_eq!(outcome.output, output);
+    assert_eq!(outcome.duration, Some(10.0));
+    assert!(output.is_file());
+
+    let spawned = fake.spawned();
+    assert_eq!(spawned.len(), 1);
+
+    let argv = &spawned[0];
+    assert_eq!(argv[0], "ffmpeg");
+    assert_eq!(&argv[4..7], &["-nostats", "-progress", "pipe:1"]);
+
+    // ffmpeg writes to a temporary file which is moved into place.
+    let temp = argv.last().unwrap();
+    assert_ne!(temp, &output.to_string_lossy());
+    assert!(temp.ends_with(".mp4"));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn output_is_not_input() {
+    let fake = Arc::new(Fake::software());
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+    let dir = output_dir("output-is-input");
+    let input = dir.join("clip.mp4");
+    fs::write(&input, b"source").unwrap();
+
+    // The same file, through another path.
+    let output = dir.join(".").join("clip.mp4");
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        &input,
+        &output,
+    )
+    .source(&source(SMALL));
+
+    let events = Recorder::default();
+    let error = ffmpeg.transcode(&job, &events).unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        format!("output is the same file as the input: {}", output.display())
+    );
+
+    assert!(fake.spawned().is_empty());
+    assert_eq!(fs::read(&input).unwrap(), b"source");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn archive_warns_about_streams() {
+    let fake = Arc::new(Fake {
+        codecs: vec!["libx265"],
+        ..Fake::default()
+    });
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+
+    let info = source(
+        r#"{
+            "format": {"format_name": "matroska,webm", "duration": "60.000000"},
+            "streams": [
+                {"index": 0, "codec_type": "video", "codec_name": "h264", "width": 1920, "height": 1080},
+                {"index": 1, "codec_type": "audio", "codec_name": "pcm_s24le", "channels": 2},
+                {"index": 2, "codec_type": "subtitle", "codec_name": "hdmv_pgs_subtitle"}
+            ]
+        }"#,
+    );
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::Archive, Backend::Software),
+        "in.mkv",
+        "out.mp4",
+    )
+    .source(&info);
+
+    let events = Recorder::default();
+    ffmpeg.dry_run(&job, &events).unwrap();
+
+    assert_eq!(
+        *events.warnings.lock().unwrap(),
+        [
+            "audio stream 0:1 (pcm_s24le) can't be copied into mp4, transcoding it to aac",
+            "subtitle stream 0:2 (hdmv_pgs_subtitle) can't be converted for mp4, dropping it",
+        ]
+    );
+
+    // Nothing is dropped from streams which are mapped explicitly.
+    let events = Recorder::default();
+    ffmpeg.dry_run(&job.map("0:0"), &events).unwrap();
+    assert!(events.warnings.lock().unwrap().is_empty());
+}
+
+#[test]
+fn two_pass_transcode() {
+    let fake = Arc::new(Fake::software());
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+    let dir = output_dir("two-pass");
+    let output = dir.join("out.webm");
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::WebM, Backend::Software),
+        "in.mkv",
+        &output,
+    )
+    .source(&source(SMALL));
+
+    let events = Recorder::default();
+    ffmpeg.transcode(&job, &events).unwrap();
+
+    let spawned = fake.spawned();
+    assert_eq!(spawned.len(), 2);
+
+    // The first pass only analyzes the video.
+    assert!(spawned[0].ends_with(&[
+        String::from("-an"),
+        String::from("-f"),
+        String::from("null"),
+        String::from("-")
+    ]));
+    assert!(spawned[1].last().unwrap().ends_with(".webm"));
+
+    let logs = spawned
+        .iter()
+        .map(|argv| {
+            let at = argv.iter().position(|a| a == "-passlogfile").unwrap();
+            PathBuf::from(&argv[at + 1])
+        })
+        .collect::<Vec<_>>();
+
+    // The statistics are shared between passes, and are kept out of the output directory.
+    assert_eq!(logs[0], logs[1]);
+    assert!(!logs[0].starts_with(&dir));
+    assert!(!logs[0].parent().unwrap().exists());
+
+    // Only the output is left behind.
+    let files = fs::read_dir(&dir)
+        .unwrap()
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert_eq!(files, vec![output.file_name().unwrap().to_owned()]);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn target_size_is_retried() {
+    let fake = Arc::new(Fake {
+        output_size: 6_000_000,
+        ..Fake::software()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+    let dir = output_dir("target-size");
+    let output = dir.join("out.mp4");
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        &output,
+    )
+    .source(&source(SMALL))
+    .target_size(5_000_000);
+
+    let events = Recorder::default();
+    let error = ffmpeg.transcode(&job, &events).unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "output is 6.0 MB, which is still over the target size of 5.0 MB after 3 attempts"
+    );
+
+    // Every attempt runs both passes at a lower bitrate than the one before.
+    let bitrates = fake
+        .spawned()
+        .iter()
+        .map(|argv| {
+            let at = argv.iter().position(|a| a == "-b:v").unwrap();
+            argv[at + 1].clone()
+        })
+        .collect::<Vec<_>>();
+
+    assert_eq!(
+        bitrates,
+        ["3496k", "3496k", "2767k", "2767k", "2191k", "2191k"]
+    );
+
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn target_size_retry_is_not_too_small() {
+    let fake = Arc::new(Fake {
+        output_size: 3_000_000,
+        ..Fake::software()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+    let dir = output_dir("target-size-too-small");
+    let output = dir.join("out.mp4");
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        &output,
+    )
+    .source(&source(SMALL))
+    .target_size(700_000);
+
+    let events = Recorder::default();
+    let error = ffmpeg.transcode(&job, &events).unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "target size 0.7 MB is too small, it would need a video bitrate of 35k"
+    );
+
+    // Only the first attempt ran.
+    assert_eq!(fake.spawned().len(), 2);
+
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn target_quality_searches_crf() {
+    let fake = Arc::new(Fake {
+        codecs: {
+            let mut codecs = Fake::software().codecs;
+            codecs.push("libvmaf");
+            codecs
+        },
+        vmaf: Some(|crf| 120.0 - f64::from(crf)),
+        ..Fake::default()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+    let dir = output_dir("target-quality");
+    let output = dir.join("out.mp4");
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        &output,
+    )
+    .source(&source(SMALL))
+    .target_quality(95.0);
+
+    let events = Recorder::default();
+    ffmpeg.transcode(&job, &events).unwrap();
+
+    let calls = fake.calls.lock().unwrap().clone();
+
+    let crf = |argv: &[String]| {
+        let at = argv.iter().position(|a| a == "-crf").unwrap();
+        argv[at + 1].clone()
+    };
+
+    // Three samples are encoded and scored for each CRF, the bounds of the range first.
+    let samples = calls
+        .iter()
+        .filter(|c| c.iter().any(|a| a == "matroska"))
+        .map(|c| crf(c))
+        .collect::<Vec<_>>();
+
+    assert_eq!(samples.len(), 12);
+    assert_eq!(
+        samples.chunks(3).map(|c| c[0].as_str()).collect::<Vec<_>>(),
+        ["16", "34", "25", "26"]
+    );
+
+    let scores = calls
+        .iter()
+        .filter(|c| c.iter().any(|a| a == "-lavfi"))
+        .count();
+    assert_eq!(scores, 12);
+
+    assert_eq!(
+        *events.quality.lock().unwrap(),
+        [
+            (16, 104.0, Metric::Vmaf),
+            (34, 86.0, Metric::Vmaf),
+            (25, 95.0, Metric::Vmaf),
+            (26, 94.0, Metric::Vmaf)
+        ]
+    );
+
+    // The output is encoded at the highest CRF reaching the target.
+    let spawned = fake.spawned();
+    assert_eq!(spawned.len(), 1);
+    assert_eq!(crf(&spawned[0]), "25");
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn target_quality_needs_crf() {
+    let fake = Arc::new(Fake::software());
+    let ffmpeg = Ffmpeg::with_runner(fake, "ffmpeg", Some(Backend::Software)).unwrap();
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Nvidia),
+        "in.mkv",
+        "out.mp4",
+    )
+    .source(&source(SMALL))
+    .target_quality(95.0);
+
+    let events = Recorder::default();
+    let error = ffmpeg.transcode(&job, &events).unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "format YouTube doesn't encode video at a constant quality (video.crf), so it can't \
+         reach a target quality"
+    );
+}
+
+#[test]
+fn target_quality_falls_back_to_ssim() {
+    let fake = Arc::new(Fake {
+        codecs: {
+            let mut codecs = Fake::software().codecs;
+            codecs.push("ssim");
+            codecs
+        },
+        vmaf: Some(|crf| 120.0 - f64::from(crf)),
+        ..Fake::default()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+    let dir = output_dir("target-quality-ssim");
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        dir.join("out.mp4"),
+    )
+    .source(&source(SMALL))
+    .target_quality(95.0);
+
+    let events = Recorder::default();
+    ffmpeg.transcode(&job, &events).unwrap();
+
+    assert_eq!(
+        events.warnings.lock().unwrap()[0],
+        "ffmpeg doesn't have libvmaf, so samples are scored with SSIM scaled to 0-100 instead, \
+         which is more lenient than VMAF"
+    );
+
+    // Samples are scored with the ssim filter, whose score is scaled to 0-100.
+    assert!(fake
+        .calls
+        .lock()
+        .unwrap()
+        .iter()
+        .filter_map(|c| c.iter().skip_while(|a| *a != "-lavfi").nth(1))
+        .all(|graph| graph.ends_with("[distorted][reference]ssim")));
+
+    let quality = events.quality.lock().unwrap();
+    assert_eq!(quality.len(), 4);
+    assert!(quality.iter().all(|&(_, _, metric)| metric == Metric::Ssim));
+    assert!((quality[2].1 - 95.0).abs() < 1e-6);
+
+    let spawned = fake.spawned();
+    assert_eq!(spawned.len(), 1);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn target_quality_needs_a_metric() {
+    let fake = Arc::new(Fake::software());
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        "out.mp4",
+    )
+    .source(&source(SMALL))
+    .target_quality(95.0);
+
+    let error = ffmpeg.transcode(&job, &Recorder::default()).unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "a target quality needs an ffmpeg with the libvmaf or ssim filter, which ffmpeg doesn't \
+         have"
+    );
+
+    assert!(!fake
+        .calls
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|c| c.iter().any(|a| a == "in.mkv")));
+}
+
+#[test]
+fn target_quality_search_is_cancelled() {
+    /// Cancels the job as soon as the first CRF has been scored.
+    struct CancelOnQuality(Cancel);
+
+    impl Events for CancelOnQuality {
+        fn quality(&self, _: &Path, _: u32, _: f64, _: Metric) {
+            self.0.cancel();
+        }
+    }
+
+    let fake = Arc::new(Fake {
+        codecs: {
+            let mut codecs = Fake::software().codecs;
+            codecs.push("libvmaf");
+            codecs
+        },
+        vmaf: Some(|crf| 120.0 - f64::from(crf)),
+        ..Fake::default()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake.clone(), "ffmpeg", Some(Backend::Software)).unwrap();
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        "out.mp4",
+    )
+    .source(&source(SMALL))
+    .target_quality(95.0);
+
+    let events = CancelOnQuality(ffmpeg.cancel().clone());
+    let error = ffmpeg.transcode(&job, &events).unwrap_err();
+    assert!(matches!(error, TranscodeError::Interrupted));
+
+    // Only the samples of the first CRF were encoded and scored.
+    let samples = fake
+        .calls
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|c| c.iter().any(|a| a == "in.mkv"))
+        .count();
+
+    assert_eq!(samples, 6);
+    assert!(fake.spawned().is_empty());
+}
+
+#[test]
+fn failed_transcode_is_classified() {
+    let fake = Arc::new(Fake {
+        stderr: String::from(
+            "[vost#0:0 @ 0x5583] Unknown encoder 'libx264'\n\
+             Error selecting an encoder\n",
+        ),
+        code: 1,
+        ..Fake::software()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake, "ffmpeg", Some(Backend::Software)).unwrap();
+    let dir = output_dir("failed");
+    let output = dir.join("out.mp4");
+
+    let job = TranscodeJob::new(
+        Format::builtin(Builtin::YouTube, Backend::Software),
+        "in.mkv",
+        &output,
+    );
+
+    let events = Recorder::default();
+    let error = ffmpeg.transcode(&job, &events).unwrap_err();
+
+    match error {
+        TranscodeError::Failed { class, lines, .. } => {
+            assert_eq!(class, Class::UnknownEncoder(String::from("libx264")));
+            assert_eq!(lines.len(), 2);
+        }
+        other => panic!("unexpected error: {}", other),
+    }
+
+    // What ffmpeg printed is only part of the error.
+    assert!(events.warnings.lock().unwrap().is_empty());
+    assert!(!output.exists());
+    assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn too_old_ffmpeg() {
+    let fake = Arc::new(Fake {
+        banner: Some("ffmpeg version 3.4.8-0ubuntu0.2 Copyright (c) 2000-2020"),
+        ..Fake::software()
+    });
+
+    let error = Ffmpeg::with_runner(fake, "/opt/ffmpeg-3/ffmpeg", Some(Backend::Software))
+        .err()
+        .unwrap();
+
+    assert_eq!(
+        error.to_string(),
+        "ffmpeg 3.4.8 is too old: /opt/ffmpeg-3/ffmpeg: tessie needs ffmpeg 4.0 or newer"
+    );
+}
+
+#[test]
+fn too_old_for_preset() {
+    let fake = Arc::new(Fake {
+        banner: Some("ffmpeg version n5.1.2 Copyright (c) 2000-2022"),
+        ..Fake::software()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake, "ffmpeg", Some(Backend::Software)).unwrap();
+    assert_eq!(ffmpeg.version(), Some("5.1.2".parse().unwrap()));
+
+    let mut format = Format::builtin(Builtin::YouTube, Backend::Software);
+    format.settings.min_ffmpeg_version = Some(String::from("5.1"));
+    ffmpeg.check(&format).unwrap();
+
+    format.settings.min_ffmpeg_version = Some(String::from("6"));
+    let error = ffmpeg.check(&format).unwrap_err();
+
+    assert_eq!(
+        error.to_string(),
+        "format YouTube needs ffmpeg 6.0 or newer, but ffmpeg is version 5.1.2"
+    );
+}
+
+#[test]
+fn git_builds_have_no_version() {
+    let fake = Arc::new(Fake {
+        banner: Some("ffmpeg version N-112345-g1234abcd-20231010 Copyright (c) 2000-2023"),
+        ..Fake::software()
+    });
+
+    let ffmpeg = Ffmpeg::with_runner(fake, "ffmpeg", Some(Backend::Software)).unwrap();
+    assert_eq!(ffmpeg.version(), None);
+
+    let mut format = Format::builtin(Builtin::YouTube, Backend::Software);
+    format.settings.min_ffmpeg_version = Some(String::from("7.0"));
+    ffmpeg.check(&format).unwrap();
+}
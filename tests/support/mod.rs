@@ -0,0 +1,244 @@
+//! Shared helpers for tests, including a fake runner which never runs ffmpeg.
+
+#![allow(dead_code)]
+
+use std::{
+    ffi::OsString,
+    fs,
+    io::{self, Cursor},
+    path::Path,
+    process::{ExitStatus, Output},
+    sync::Mutex,
+};
+use tessie::{Events, MediaInfo, Metric, Process, Runner, Spawned};
+
+/// A 4K source at 120 fps, which exceeds the limits of every built-in format.
+pub const LARGE: &str = r#"{
+    "format": {"format_name": "matroska,webm", "duration": "120.000000"},
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "h264", "width": 3840, "height": 2160, "r_frame_rate": "120/1", "avg_frame_rate": "120/1"},
+        {"index": 1, "codec_type": "audio", "codec_name": "opus", "channels": 2, "sample_rate": "48000"}
+    ]
+}"#;
+
+/// A 720p source at 30 fps, which fits within the limits of YouTube.
+pub const SMALL: &str = r#"{
+    "format": {"format_name": "mov,mp4,m4a,3gp,3g2,mj2", "duration": "10.000000"},
+    "streams": [
+        {"index": 0, "codec_type": "video", "codec_name": "h264", "width": 1280, "height": 720, "r_frame_rate": "30/1", "avg_frame_rate": "30/1"},
+        {"index": 1, "codec_type": "audio", "codec_name": "aac", "channels": 2, "sample_rate": "44100"}
+    ]
+}"#;
+
+/// Parse one of the canned sources.
+pub fn source(json: &str) -> MediaInfo {
+    MediaInfo::from_json(json.as_bytes()).expect("canned source should parse")
+}
+
+/// Convert arguments into strings for easy comparison.
+pub fn strings(argv: &[OsString]) -> Vec<String> {
+    argv.iter()
+        .map(|a| a.to_string_lossy().into_owned())
+        .collect()
+}
+
+/// Construct an exit status with the given code.
+pub fn exit_status(code: i32) -> ExitStatus {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt as _;
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::ExitStatusExt as _;
+        ExitStatus::from_raw(code as u32)
+    }
+}
+
+/// A runner which records every command and replays canned output instead of running ffmpeg.
+#[derive(Default)]
+pub struct Fake {
+    /// The first line printed by `ffmpeg -version`.
+    pub banner: Option<&'static str>,
+    /// Encoders, decoders and filters reported by `ffmpeg -encoders` and friends.
+    pub codecs: Vec<&'static str>,
+    /// Methods reported by `ffmpeg -hwaccels`.
+    pub hwaccels: Vec<&'static str>,
+    /// What a spawned ffmpeg writes to stdout.
+    pub stdout: String,
+    /// What a spawned ffmpeg writes to stderr.
+    pub stderr: String,
+    /// The exit code of a spawned ffmpeg.
+    pub code: i32,
+    /// The size of the output written by a spawned ffmpeg.
+    pub output_size: usize,
+    /// The VMAF score of a sample encoded with the given CRF, which is printed by the ssim filter
+    /// scaled to 0-1 if that is what scores the sample.
+    pub vmaf: Option<fn(u32) -> f64>,
+    /// The CRF of the last sample encoded.
+    pub crf: Mutex<Option<u32>>,
+    /// Every command run so far.
+    pub calls: Mutex<Vec<Vec<String>>>,
+}
+
+impl Fake {
+    /// A fake with everything the software backend of the built-in formats needs.
+    pub fn software() -> Fake {
+        Fake {
+            codecs: vec![
+                "libx264",
+                "aac",
+                "libvpx-vp9",
+                "libopus",
+                "gif",
+                "scale",
+                "fps",
+                "split",
+                "palettegen",
+                "paletteuse",
+            ],
+            ..Fake::default()
+        }
+    }
+
+    /// The commands which were spawned, as opposed to run to completion.
+    pub fn spawned(&self) -> Vec<Vec<String>> {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|c| c.iter().any(|a| a == "-progress"))
+            .cloned()
+            .collect()
+    }
+
+    fn record(&self, argv: &[OsString]) -> Vec<String> {
+        let argv = strings(argv);
+        self.calls.lock().unwrap().push(argv.clone());
+        argv
+    }
+}
+
+impl Runner for Fake {
+    fn output(&self, argv: &[OsString]) -> io::Result<Output> {
+        let argv = self.record(argv);
+
+        let table = |names: &[&str]| {
+            names
+                .iter()
+                .map(|n| format!(" V..... {} description\n", n))
+                .collect::<String>()
+        };
+
+        let stdout = match argv.last().map(String::as_str) {
+            Some("-version") => format!(
+                "{}\n",
+                self.banner
+                    .unwrap_or("ffmpeg version 6.1 Copyright (c) 2000-2023")
+            ),
+            Some("-encoders") | Some("-decoders") | Some("-filters") => table(&self.codecs),
+            Some("-hwaccels") => format!(
+                "Hardware acceleration methods:\n{}\n",
+                self.hwaccels.join("\n")
+            ),
+            _ => String::new(),
+        };
+
+        Ok(Output {
+            // Test encodes of hardware backends fail, like they do without a device.
+            status: exit_status(if argv.iter().any(|a| a == "lavfi") {
+                1
+            } else {
+                0
+            }),
+            stdout: stdout.into_bytes(),
+            stderr: Vec::new(),
+        })
+    }
+
+    fn spawn(&self, argv: &[OsString]) -> io::Result<Spawned> {
+        let argv = self.record(argv);
+
+        // Like ffmpeg, create the output so that it can be moved into place, and the statistics
+        // of a multi-pass encode.
+        if self.code == 0 {
+            if let Some(log) = argv.iter().skip_while(|a| *a != "-passlogfile").nth(1) {
+                fs::write(format!("{}-0.log", log), b"")?;
+            }
+
+            if let Some(output) = argv.last().filter(|o| *o != "-") {
+                fs::write(Path::new(output), vec![0; self.output_size])?;
+            }
+        }
+
+        let mut stderr = self.stderr.clone();
+
+        // Samples are encoded before they are scored, so the score is decided by the CRF of the
+        // sample encoded last.
+        if let Some(at) = argv.iter().position(|a| a == "-crf") {
+            *self.crf.lock().unwrap() = argv[at + 1].parse().ok();
+        }
+
+        if let (Some(vmaf), Some(at)) = (self.vmaf, argv.iter().position(|a| a == "-lavfi")) {
+            let crf = self
+                .crf
+                .lock()
+                .unwrap()
+                .expect("a sample should be encoded first");
+
+            if argv[at + 1].ends_with("libvmaf") {
+                stderr.push_str(&format!(
+                    "[Parsed_libvmaf_4 @ 0x1] VMAF score: {:.6}\n",
+                    vmaf(crf)
+                ));
+            } else {
+                stderr.push_str(&format!(
+                    "[Parsed_ssim_4 @ 0x1] SSIM Y:0.990000 (20.0) All:{:.6} (18.2)\n",
+                    vmaf(crf) / 100.0
+                ));
+            }
+        }
+
+        Ok(Spawned {
+            stdout: Box::new(Cursor::new(self.stdout.clone().into_bytes())),
+            stderr: Box::new(Cursor::new(stderr.into_bytes())),
+            process: Box::new(FakeProcess { code: self.code }),
+        })
+    }
+}
+
+/// A process which has already exited with the given code.
+struct FakeProcess {
+    code: i32,
+}
+
+impl Process for FakeProcess {
+    fn stop(&mut self) -> io::Result<ExitStatus> {
+        Ok(exit_status(self.code))
+    }
+
+    fn wait(&mut self) -> io::Result<ExitStatus> {
+        Ok(exit_status(self.code))
+    }
+}
+
+/// Records the events reported while running a job.
+#[derive(Default)]
+pub struct Recorder {
+    /// Every warning, in order.
+    pub warnings: Mutex<Vec<String>>,
+    /// The CRF, score and metric of every scored set of samples, in order.
+    pub quality: Mutex<Vec<(u32, f64, Metric)>>,
+}
+
+impl Events for Recorder {
+    fn warning(&self, _: &Path, message: &str) {
+        self.warnings.lock().unwrap().push(message.to_string());
+    }
+
+    fn quality(&self, _: &Path, crf: u32, score: f64, metric: Metric) {
+        self.quality.lock().unwrap().push((crf, score, metric));
+    }
+}